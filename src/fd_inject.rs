@@ -0,0 +1,147 @@
+//! Real-fd injection for the ptrace backend. The seccomp-notify backend gets
+//! this for free via `SECCOMP_IOCTL_NOTIF_ADDFD` (see `seccomp_backend`);
+//! ptrace has no equivalent "hand the tracee a new fd" primitive, so instead:
+//! the supervisor `sendmsg`s the already-opened cache file fd with
+//! `SCM_RIGHTS` over a socketpair set up before `fork`, then forces the
+//! *stopped* tracee to run its own `recvmsg` against that socket by
+//! temporarily pointing its registers at a scratch `msghdr` below its stack
+//! and re-running the `syscall` instruction it just trapped on. Once the
+//! injected call returns, the tracee's original registers are restored — the
+//! only visible effect is that it now owns a new fd.
+
+use nix::sys::ptrace;
+use nix::sys::signal::Signal;
+use nix::sys::socket::{sendmsg, ControlMessage, MsgFlags};
+use nix::sys::wait::{waitpid, WaitStatus};
+use nix::unistd::Pid;
+use std::io::IoSlice;
+use std::os::fd::RawFd;
+
+use crate::PtraceError;
+
+const SYS_RECVMSG: u64 = 47;
+// Comfortably below the stack pointer: the tracee is fully stopped for the
+// duration of the injection, so nothing can race us for this scratch space
+// the way a signal handler normally could in the x86-64 "red zone".
+const SCRATCH_OFFSET: u64 = 512;
+const CMSG_LEN: usize = 24; // CMSG_SPACE(sizeof(int)) on x86-64.
+
+/// Mirrors enough of glibc's `struct msghdr` (x86-64) to describe a message
+/// with no data, just a one-fd `SCM_RIGHTS` control buffer.
+#[repr(C)]
+struct MsgHdr {
+    msg_name: u64,
+    msg_namelen: u32,
+    _pad0: u32,
+    msg_iov: u64,
+    msg_iovlen: u64,
+    msg_control: u64,
+    msg_controllen: u64,
+    msg_flags: i32,
+    _pad1: u32,
+}
+
+fn struct_bytes<T>(value: &T) -> &[u8] {
+    unsafe { std::slice::from_raw_parts((value as *const T) as *const u8, std::mem::size_of::<T>()) }
+}
+
+/// Sends `local_fd` to `tracee_sock_fd` over the tracer's end (`tracer_sock`)
+/// of a pre-existing `SCM_RIGHTS`-capable socketpair, then forces the
+/// stopped `pid` to `recvmsg` it. Returns the new fd number inside the
+/// tracee.
+pub fn inject_fd(
+    pid: Pid,
+    tracer_sock: RawFd,
+    tracee_sock_fd: i32,
+    local_fd: RawFd,
+) -> Result<i32, PtraceError> {
+    sendmsg::<()>(
+        tracer_sock,
+        &[IoSlice::new(&[0u8])],
+        &[ControlMessage::ScmRights(&[local_fd])],
+        MsgFlags::empty(),
+        None,
+    )
+    .map_err(|e| PtraceError::PtraceOperation(format!("sendmsg to tracee failed: {}", e)))?;
+
+    recvmsg_in_tracee(pid, tracee_sock_fd)
+}
+
+fn recvmsg_in_tracee(pid: Pid, tracee_sock_fd: i32) -> Result<i32, PtraceError> {
+    let saved_regs = ptrace::getregs(pid)
+        .map_err(|e| PtraceError::PtraceOperation(format!("getregs failed: {}", e)))?;
+
+    let msghdr_addr = saved_regs.rsp - SCRATCH_OFFSET;
+    let cmsg_addr = msghdr_addr + 64;
+
+    let msghdr = MsgHdr {
+        msg_name: 0,
+        msg_namelen: 0,
+        _pad0: 0,
+        msg_iov: 0,
+        msg_iovlen: 0,
+        msg_control: cmsg_addr,
+        msg_controllen: CMSG_LEN as u64,
+        msg_flags: 0,
+        _pad1: 0,
+    };
+    crate::write_data_to_child(pid, msghdr_addr, struct_bytes(&msghdr))?;
+
+    let mut regs = saved_regs;
+    regs.orig_rax = SYS_RECVMSG;
+    regs.rax = SYS_RECVMSG;
+    regs.rdi = tracee_sock_fd as u64;
+    regs.rsi = msghdr_addr;
+    regs.rdx = 0; // flags
+    // `rip` sits right after the 2-byte `syscall` instruction at this
+    // syscall-exit stop; rewind it so the next `PTRACE_SYSCALL` re-executes a
+    // syscall with the registers we just set, instead of single-stepping
+    // past whatever instruction actually follows.
+    regs.rip = saved_regs.rip - 2;
+
+    ptrace::setregs(pid, regs)
+        .map_err(|e| PtraceError::PtraceOperation(format!("setregs failed: {}", e)))?;
+
+    step_to_next_stop(pid)?; // injected syscall's entry stop
+    step_to_next_stop(pid)?; // injected syscall's exit stop
+
+    let result_regs = ptrace::getregs(pid)
+        .map_err(|e| PtraceError::PtraceOperation(format!("getregs failed: {}", e)))?;
+    let recvmsg_result = result_regs.rax as i64;
+
+    let fd = if recvmsg_result >= 0 {
+        let cmsg_bytes = crate::read_data_from_child(pid, cmsg_addr, CMSG_LEN)?;
+        // `struct cmsghdr` is { cmsg_len: u64, cmsg_level: i32, cmsg_type: i32 },
+        // immediately followed by the fd itself.
+        let fd_bytes: [u8; 4] = cmsg_bytes[16..20].try_into().unwrap();
+        Ok(i32::from_ne_bytes(fd_bytes))
+    } else {
+        Err(PtraceError::PtraceOperation(format!(
+            "injected recvmsg failed: errno {}",
+            -recvmsg_result
+        )))
+    };
+
+    ptrace::setregs(pid, saved_regs)
+        .map_err(|e| PtraceError::PtraceOperation(format!("setregs restore failed: {}", e)))?;
+
+    fd
+}
+
+fn step_to_next_stop(pid: Pid) -> Result<(), PtraceError> {
+    ptrace::syscall(pid, None)
+        .map_err(|e| PtraceError::PtraceOperation(format!("syscall step failed: {}", e)))?;
+
+    // Wait specifically on `pid`, not the untargeted `waitpid(-1, ...)` that
+    // a bare `wait()` performs: with other tracees live (forked/cloned
+    // descendants under `PTRACE_O_TRACEFORK` et al.), an untargeted wait
+    // could reap a different pid's stop mid-injection and never resume it.
+    match waitpid(pid, None) {
+        Ok(WaitStatus::Stopped(_, Signal::SIGTRAP)) | Ok(WaitStatus::PtraceSyscall(_)) => Ok(()),
+        Ok(status) => Err(PtraceError::PtraceOperation(format!(
+            "unexpected wait status during fd injection: {:?}",
+            status
+        ))),
+        Err(e) => Err(PtraceError::PtraceOperation(format!("wait failed: {}", e))),
+    }
+}
@@ -1,18 +1,26 @@
 use nix::sys::ptrace;
 use nix::sys::signal::Signal;
+use nix::sys::socket::{socketpair, AddressFamily, SockFlag, SockType};
+use nix::sys::uio::{process_vm_readv, process_vm_writev, RemoteIoVec};
 use nix::sys::wait::{wait, WaitStatus};
-use nix::unistd::{fork, ForkResult, Pid};
+use nix::unistd::{dup2, fork, ForkResult, Pid};
+use std::collections::{HashMap, HashSet};
 use std::env;
 use std::fmt;
+use std::io::{IoSlice, IoSliceMut};
+use std::os::fd::{AsRawFd, OwnedFd, RawFd};
 use std::os::unix::process::CommandExt;
 use std::process::{exit, Command};
 use tokio::runtime::Runtime;
 
+mod fd_inject;
+mod path_rewrite;
+mod seccomp_backend;
 mod websocket_fs;
 #[cfg(test)]
 mod websocket_fs_tests;
 
-use websocket_fs::WebSocketFileSystem;
+use websocket_fs::{DirEntry, FileMetadata, FileType, WebSocketFileSystem};
 
 #[derive(Debug)]
 enum PtraceError {
@@ -44,14 +52,30 @@ const SYS_READ: i64 = 0;
 const SYS_WRITE: i64 = 1;
 const SYS_CLOSE: i64 = 3;
 const SYS_LSEEK: i64 = 8;
+const SYS_GETDENTS64: i64 = 217;
+const SYS_UNLINKAT: i64 = 263;
+const SYS_RENAMEAT: i64 = 264;
+const SYS_NEWFSTATAT: i64 = 262;
+const SYS_GETPID: u64 = 39;
 
 const MAX_STRING_LENGTH: usize = 4096;
 const MAX_BUFFER_SIZE: usize = 1024 * 1024; // 1MB
+const PAGE_SIZE: usize = 4096;
 
-fn run_child(program: &str, args: &[String]) -> Result<(), PtraceError> {
+/// Fixed fd number the tracee's end of the fd-injection socketpair is
+/// `dup2`'d to before `exec`, so the tracer can drive `recvmsg` against it by
+/// number without any further coordination (see `fd_inject`).
+const FD_INJECT_SOCK_FD: RawFd = 350;
+
+fn run_child(program: &str, args: &[String], fd_inject_sock: Option<OwnedFd>) -> Result<(), PtraceError> {
     ptrace::traceme()
         .map_err(|e| PtraceError::PtraceOperation(format!("traceme failed: {}", e)))?;
 
+    if let Some(sock) = fd_inject_sock {
+        dup2(sock.as_raw_fd(), FD_INJECT_SOCK_FD)
+            .map_err(|e| PtraceError::PtraceOperation(format!("dup2 failed: {}", e)))?;
+    }
+
     let mut cmd = Command::new(program);
     for arg in args {
         cmd.arg(arg);
@@ -62,6 +86,102 @@ fn run_child(program: &str, args: &[String]) -> Result<(), PtraceError> {
     exit(1);
 }
 
+/// Reads `len` bytes from the tracee's memory at `addr` in a single
+/// `process_vm_readv` call. Returns `None` on any failure (e.g. `EFAULT` for
+/// an unmapped remote page, `ESRCH` if the tracee died) so the caller can
+/// fall back to the word-by-word ptrace path.
+fn read_chunk_fast(pid: Pid, addr: u64, len: usize) -> Option<Vec<u8>> {
+    let mut buf = vec![0u8; len];
+    let remote = [RemoteIoVec {
+        base: addr as usize,
+        len,
+    }];
+    let mut local = [IoSliceMut::new(&mut buf)];
+
+    match process_vm_readv(pid, &mut local, &remote) {
+        Ok(n) if n == len => Some(buf),
+        _ => None,
+    }
+}
+
+/// Writes `data` into the tracee's memory at `addr` in a single
+/// `process_vm_writev` call. Returns `false` on any failure so the caller
+/// can fall back to the word-by-word ptrace path.
+fn write_chunk_fast(pid: Pid, addr: u64, data: &[u8]) -> bool {
+    let remote = [RemoteIoVec {
+        base: addr as usize,
+        len: data.len(),
+    }];
+    let local = [IoSlice::new(data)];
+
+    matches!(process_vm_writev(pid, &local, &remote), Ok(n) if n == data.len())
+}
+
+/// Reads exactly `len` bytes from the tracee's memory at `addr`, one 8-byte
+/// `PTRACE_PEEKDATA` word at a time. Used as the fallback when
+/// `process_vm_readv` isn't available or fails.
+fn read_chunk_ptrace(pid: Pid, addr: u64, len: usize) -> Result<Vec<u8>, PtraceError> {
+    let mut result = Vec::with_capacity(len);
+    let mut current_addr = addr;
+    let mut remaining = len;
+
+    while remaining > 0 {
+        let word = ptrace::read(pid, current_addr as *mut std::ffi::c_void)
+            .map_err(|_| PtraceError::MemoryRead)? as u64;
+
+        let bytes_to_read = remaining.min(8);
+        for i in 0..bytes_to_read {
+            let byte = ((word >> (i * 8)) & 0xff) as u8;
+            result.push(byte);
+        }
+
+        remaining -= bytes_to_read;
+        current_addr += 8;
+    }
+
+    Ok(result)
+}
+
+/// Writes all of `data` into the tracee's memory at `addr`, one 8-byte
+/// `PTRACE_POKEDATA` word at a time, read-modify-writing the final partial
+/// word so surrounding bytes aren't clobbered. Used as the fallback when
+/// `process_vm_writev` isn't available or fails.
+fn write_chunk_ptrace(pid: Pid, addr: u64, data: &[u8]) -> Result<(), PtraceError> {
+    let mut current_addr = addr;
+
+    for chunk in data.chunks(8) {
+        let word: u64 = if chunk.len() == 8 {
+            let mut word: u64 = 0;
+            for (i, &byte) in chunk.iter().enumerate() {
+                word |= (byte as u64) << (i * 8);
+            }
+            word
+        } else {
+            // A partial final word: preserve the bytes past the end of
+            // `data` by reading the existing word first.
+            let existing = ptrace::read(pid, current_addr as *mut std::ffi::c_void)
+                .map_err(|_| PtraceError::MemoryRead)? as u64;
+            let mut word = existing;
+            for (i, &byte) in chunk.iter().enumerate() {
+                word = (word & !(0xffu64 << (i * 8))) | ((byte as u64) << (i * 8));
+            }
+            word
+        };
+
+        unsafe {
+            ptrace::write(
+                pid,
+                current_addr as *mut std::ffi::c_void,
+                word as usize as *mut std::ffi::c_void,
+            )
+        }
+        .map_err(|_| PtraceError::MemoryWrite)?;
+        current_addr += 8;
+    }
+
+    Ok(())
+}
+
 fn read_string(pid: Pid, addr: u64) -> Result<String, PtraceError> {
     if addr == 0 {
         return Err(PtraceError::InvalidAddress);
@@ -69,28 +189,35 @@ fn read_string(pid: Pid, addr: u64) -> Result<String, PtraceError> {
 
     let mut result = Vec::new();
     let mut current_addr = addr;
-    let max_iterations = MAX_STRING_LENGTH / 8 + 1;
 
-    for _ in 0..max_iterations {
-        let word = ptrace::read(pid, current_addr as *mut std::ffi::c_void)
-            .map_err(|_| PtraceError::MemoryRead)? as u64;
+    loop {
+        if result.len() >= MAX_STRING_LENGTH {
+            return Err(PtraceError::StringTooLong);
+        }
 
-        for i in 0..8 {
-            let byte = ((word >> (i * 8)) & 0xff) as u8;
+        // Read up to the next page boundary at a time, so a partial read
+        // near an unmapped page still succeeds instead of faulting on a
+        // larger single call.
+        let bytes_to_page_end = PAGE_SIZE - (current_addr as usize % PAGE_SIZE);
+        let chunk_len = bytes_to_page_end.min(MAX_STRING_LENGTH - result.len());
+
+        let chunk = match read_chunk_fast(pid, current_addr, chunk_len) {
+            Some(chunk) => chunk,
+            None => read_chunk_ptrace(pid, current_addr, chunk_len)?,
+        };
+
+        for &byte in &chunk {
             if byte == 0 {
                 return Ok(String::from_utf8_lossy(&result).into_owned());
             }
-
             if result.len() >= MAX_STRING_LENGTH {
                 return Err(PtraceError::StringTooLong);
             }
-
             result.push(byte);
         }
-        current_addr += 8;
-    }
 
-    Err(PtraceError::StringTooLong)
+        current_addr += chunk.len() as u64;
+    }
 }
 
 fn read_data_from_child(pid: Pid, addr: u64, count: usize) -> Result<Vec<u8>, PtraceError> {
@@ -102,25 +229,14 @@ fn read_data_from_child(pid: Pid, addr: u64, count: usize) -> Result<Vec<u8>, Pt
         return Err(PtraceError::BufferTooLarge);
     }
 
-    let mut result = Vec::new();
-    let mut current_addr = addr;
-    let mut remaining = count;
-
-    while remaining > 0 {
-        let word = ptrace::read(pid, current_addr as *mut std::ffi::c_void)
-            .map_err(|_| PtraceError::MemoryRead)? as u64;
-
-        let bytes_to_read = remaining.min(8);
-        for i in 0..bytes_to_read {
-            let byte = ((word >> (i * 8)) & 0xff) as u8;
-            result.push(byte);
-        }
-
-        remaining -= bytes_to_read;
-        current_addr += 8;
+    if count == 0 {
+        return Ok(Vec::new());
     }
 
-    Ok(result)
+    match read_chunk_fast(pid, addr, count) {
+        Some(data) => Ok(data),
+        None => read_chunk_ptrace(pid, addr, count),
+    }
 }
 
 fn write_data_to_child(pid: Pid, addr: u64, data: &[u8]) -> Result<(), PtraceError> {
@@ -132,30 +248,108 @@ fn write_data_to_child(pid: Pid, addr: u64, data: &[u8]) -> Result<(), PtraceErr
         return Err(PtraceError::BufferTooLarge);
     }
 
-    let mut current_addr = addr;
+    if data.is_empty() {
+        return Ok(());
+    }
 
-    for chunk in data.chunks(8) {
-        let mut word: u64 = 0;
-        for (i, &byte) in chunk.iter().enumerate() {
-            word |= (byte as u64) << (i * 8);
+    if write_chunk_fast(pid, addr, data) {
+        return Ok(());
+    }
+
+    write_chunk_ptrace(pid, addr, data)
+}
+
+/// Swaps the syscall about to run for a harmless `getpid` so a fake-fd
+/// `read`/`write`/`lseek` we've already serviced in entry doesn't also hit
+/// the real kernel with a descriptor number the tracee's fd table has never
+/// heard of. `handle_syscall_exit` then overwrites `rax` with the real
+/// result once this no-op returns.
+fn neutralize_syscall(pid: Pid, mut regs: libc::user_regs_struct) -> Result<(), PtraceError> {
+    regs.orig_rax = SYS_GETPID;
+    ptrace::setregs(pid, regs)
+        .map_err(|e| PtraceError::PtraceOperation(format!("setregs failed: {}", e)))
+}
+
+/// Builds the raw `struct stat` bytes `newfstatat` writes into the tracee's
+/// buffer. Only the fields a caller checking "does this exist, is it a
+/// directory, how big is it" would look at are filled in; everything else
+/// (uid/gid/inode/block counts, ...) is left zeroed since the remote doesn't
+/// track it.
+fn stat_bytes(metadata: &FileMetadata) -> Vec<u8> {
+    let mut st: libc::stat = unsafe { std::mem::zeroed() };
+    st.st_size = metadata.size as i64;
+    st.st_mtime = metadata.mtime.unwrap_or(0);
+    st.st_nlink = 1;
+    st.st_mode = match metadata.file_type {
+        FileType::Dir => libc::S_IFDIR | 0o755,
+        FileType::Symlink => libc::S_IFLNK | 0o777,
+        FileType::File => libc::S_IFREG | 0o644,
+    };
+    unsafe {
+        std::slice::from_raw_parts(
+            &st as *const libc::stat as *const u8,
+            std::mem::size_of::<libc::stat>(),
+        )
+    }
+    .to_vec()
+}
+
+/// Encodes `entries[start..]` as a run of `linux_dirent64` records (see
+/// `linux/dirent.h`), stopping once adding another record would exceed
+/// `max_bytes`. Returns the encoded bytes and how many entries were
+/// consumed, so the caller can track a per-fd cursor across repeated
+/// `getdents64` calls the same way a real directory stream would.
+///
+/// Returns `None` if even the very first entry at `start` doesn't fit in
+/// `max_bytes`: real `getdents64` returns `-EINVAL` for a buffer too small
+/// to hold one entry, not a 0-byte read, and a 0-byte `(vec![], 0)` result
+/// here would otherwise be indistinguishable from genuine EOF
+/// (`start >= entries.len()`).
+fn dirent_bytes(entries: &[DirEntry], start: i64, max_bytes: usize) -> Option<(Vec<u8>, i64)> {
+    const HEADER_LEN: usize = 19; // d_ino(8) + d_off(8) + d_reclen(2) + d_type(1)
+
+    let mut buf = Vec::new();
+    let mut consumed = 0i64;
+
+    for (i, entry) in entries.iter().enumerate().skip(start.max(0) as usize) {
+        let name_len = entry.name.len() + 1; // + NUL
+        let reclen = (HEADER_LEN + name_len).div_ceil(8) * 8;
+        if buf.len() + reclen > max_bytes {
+            if consumed == 0 {
+                return None;
+            }
+            break;
         }
 
-        ptrace::write(pid, current_addr as *mut std::ffi::c_void, word as i64)
-            .map_err(|_| PtraceError::MemoryWrite)?;
-        current_addr += 8;
+        let d_type = match entry.file_type {
+            FileType::Dir => libc::DT_DIR,
+            FileType::Symlink => libc::DT_LNK,
+            FileType::File => libc::DT_REG,
+        };
+
+        buf.extend_from_slice(&(i as u64 + 1).to_ne_bytes()); // d_ino
+        buf.extend_from_slice(&(i as i64 + 1).to_ne_bytes()); // d_off
+        buf.extend_from_slice(&(reclen as u16).to_ne_bytes()); // d_reclen
+        buf.push(d_type);
+        buf.extend_from_slice(entry.name.as_bytes());
+        buf.resize(buf.len() + (reclen - HEADER_LEN - entry.name.len()), 0);
+        consumed += 1;
     }
 
-    Ok(())
+    Some((buf, consumed))
 }
 
 async fn handle_syscall_entry(
     pid: Pid,
+    table_id: i32,
     fs: &mut WebSocketFileSystem,
-) -> Result<(bool, Option<String>, usize), PtraceError> {
+    path_rewrite_scratch: Option<&mut Option<u64>>,
+) -> Result<(bool, Option<String>, i64, bool, i64), PtraceError> {
     let regs = ptrace::getregs(pid)
         .map_err(|e| PtraceError::PtraceOperation(format!("getregs failed: {}", e)))?;
+    let syscall_nr = regs.orig_rax as i64;
 
-    match regs.orig_rax as i64 {
+    match syscall_nr {
         SYS_OPENAT => {
             let pathname_addr = regs.rsi;
             match read_string(pid, pathname_addr) {
@@ -164,6 +358,32 @@ async fn handle_syscall_entry(
                     fs.open_file(&pathname).await.map_err(|e| {
                         PtraceError::PtraceOperation(format!("Failed to open file: {}", e))
                     })?;
+                    if let Err(e) = fs.watch(&pathname).await {
+                        eprintln!("Failed to watch {}: {}", pathname, e);
+                    }
+
+                    // Under path rewriting the cache file is opened by the
+                    // real `openat` itself, so there's nothing left for the
+                    // exit handler to fix up. The lazy-scratch-allocation
+                    // case already runs that `openat` to completion and
+                    // resumes the tracee internally, leaving no matching
+                    // exit stop; `already_resumed` tells the caller to go
+                    // straight back to waiting instead of expecting one.
+                    if let Some(scratch) = path_rewrite_scratch {
+                        let still_paired =
+                            path_rewrite::rewrite_openat_path(pid, scratch, &pathname)?;
+                        return Ok((false, None, 0, !still_paired, syscall_nr));
+                    }
+
+                    // Mark for interception so the exit handler can rewrite
+                    // the real `openat`'s return value with either the fake
+                    // fd or, under fd injection, the real one it spliced in.
+                    // Neutralize first, same as the other fake-fd syscalls
+                    // below: the real openat must never run against the
+                    // tracee's original path (creating/truncating a host
+                    // file and leaking an fd no one ever closes).
+                    neutralize_syscall(pid, regs)?;
+                    return Ok((true, Some(pathname), 0, false, syscall_nr));
                 }
                 Err(e) => {
                     eprintln!("Failed to read pathname: {}", e);
@@ -171,74 +391,254 @@ async fn handle_syscall_entry(
             }
         }
         SYS_READ => {
+            let fd = regs.rdi as i32;
+            if fs.is_fake_fd(table_id, fd) {
+                let count = (regs.rdx as usize).min(MAX_BUFFER_SIZE);
+                println!("read: fake fd={} count={}", fd, count);
+                neutralize_syscall(pid, regs)?;
+                // `pread` only returns `None` on a genuine failure (the fd
+                // vanished out from under us, or its cache file couldn't be
+                // read even after self-healing); a real empty/EOF read comes
+                // back as `Some(vec![])`, so don't conflate the two into a
+                // silent 0-byte success.
+                let Some(data) = fs.pread(table_id, fd, count).await else {
+                    eprintln!("read: failed to read cached file for fake fd={}", fd);
+                    return Ok((true, None, -(libc::EIO as i64), false, syscall_nr));
+                };
+                if !data.is_empty() {
+                    write_data_to_child(pid, regs.rsi, &data)?;
+                }
+                let new_position = fs.fd_position(table_id, fd).unwrap_or(0) + data.len();
+                fs.update_fd_position(table_id, fd, new_position);
+                return Ok((true, None, data.len() as i64, false, syscall_nr));
+            }
             println!("read: {:?}", regs.rsi);
         }
         SYS_WRITE => {
+            let fd = regs.rdi as i32;
+            if fs.is_fake_fd(table_id, fd) {
+                let count = (regs.rdx as usize).min(MAX_BUFFER_SIZE);
+                println!("write: fake fd={} count={}", fd, count);
+                let data = read_data_from_child(pid, regs.rsi, count)?;
+                neutralize_syscall(pid, regs)?;
+                // Same distinction as `pread` above: `pwrite` only returns
+                // `None` when the write itself failed, never to mean "wrote
+                // zero bytes".
+                let Some(written) = fs.pwrite(table_id, fd, &data).await else {
+                    eprintln!("write: failed to write cached file for fake fd={}", fd);
+                    return Ok((true, None, -(libc::EIO as i64), false, syscall_nr));
+                };
+                let new_position = fs.fd_position(table_id, fd).unwrap_or(0) + written;
+                fs.update_fd_position(table_id, fd, new_position);
+                return Ok((true, None, written as i64, false, syscall_nr));
+            }
             println!("write: {:?}", regs.rsi);
         }
         SYS_LSEEK => {
+            let fd = regs.rdi as i32;
+            if fs.is_fake_fd(table_id, fd) {
+                let offset = regs.rsi as i64;
+                let whence = regs.rdx as i32;
+                println!("lseek: fake fd={} offset={} whence={}", fd, offset, whence);
+                match fs.seek(table_id, fd, offset, whence) {
+                    Some(new_position) => {
+                        neutralize_syscall(pid, regs)?;
+                        return Ok((true, None, new_position as i64, false, syscall_nr));
+                    }
+                    None => {
+                        eprintln!("lseek: invalid whence/offset for fake fd={}", fd);
+                    }
+                }
+            }
             println!("lseek: {:?}", regs.rsi);
         }
         SYS_CLOSE => {
             println!("close: {:?}", regs.rsi);
             let fd = regs.rdi as i32;
-            if fs.close_file(fd) {
+            if fs.close_file(table_id, fd) {
                 println!("close: fake fd={}", fd);
-                return Ok((true, None, 0)); // Mark for interception
+                return Ok((true, None, 0, false, syscall_nr)); // Mark for interception
+            }
+        }
+        SYS_NEWFSTATAT => {
+            match read_string(pid, regs.rsi) {
+                Ok(pathname) => {
+                    println!("newfstatat: {}", pathname);
+                    neutralize_syscall(pid, regs)?;
+                    return match fs.metadata(&pathname).await {
+                        Ok(metadata) => {
+                            write_data_to_child(pid, regs.rdx, &stat_bytes(&metadata))?;
+                            Ok((true, None, 0, false, syscall_nr))
+                        }
+                        Err(e) => {
+                            eprintln!("Failed to stat {}: {}", pathname, e);
+                            Ok((true, None, -(libc::ENOENT as i64), false, syscall_nr))
+                        }
+                    };
+                }
+                Err(e) => {
+                    eprintln!("Failed to read pathname: {}", e);
+                }
+            }
+        }
+        SYS_GETDENTS64 => {
+            let fd = regs.rdi as i32;
+            if fs.is_fake_fd(table_id, fd) {
+                let count = (regs.rdx as usize).min(MAX_BUFFER_SIZE);
+                println!("getdents64: fake fd={} count={}", fd, count);
+                let Some(path) = fs.fd_path(table_id, fd).map(str::to_string) else {
+                    neutralize_syscall(pid, regs)?;
+                    return Ok((true, None, -(libc::EBADF as i64), false, syscall_nr));
+                };
+
+                neutralize_syscall(pid, regs)?;
+                return match fs.read_dir(&path).await {
+                    Ok(entries) => {
+                        let start = fs.fd_position(table_id, fd).unwrap_or(0) as i64;
+                        match dirent_bytes(&entries, start, count) {
+                            Some((buf, consumed)) => {
+                                if !buf.is_empty() {
+                                    write_data_to_child(pid, regs.rsi, &buf)?;
+                                }
+                                fs.update_fd_position(table_id, fd, (start + consumed) as usize);
+                                Ok((true, None, buf.len() as i64, false, syscall_nr))
+                            }
+                            None => Ok((true, None, -(libc::EINVAL as i64), false, syscall_nr)),
+                        }
+                    }
+                    Err(e) => {
+                        eprintln!("Failed to read_dir {}: {}", path, e);
+                        Ok((true, None, -(libc::EIO as i64), false, syscall_nr))
+                    }
+                };
+            }
+            println!("getdents64: {:?}", regs.rsi);
+        }
+        SYS_UNLINKAT => {
+            match read_string(pid, regs.rsi) {
+                Ok(pathname) => {
+                    println!("unlinkat: {}", pathname);
+                    let result = fs.remove(&pathname, false).await;
+                    neutralize_syscall(pid, regs)?;
+                    let ret = match result {
+                        Ok(()) => 0,
+                        Err(e) => {
+                            eprintln!("Failed to remove {}: {}", pathname, e);
+                            -(libc::ENOENT as i64)
+                        }
+                    };
+                    return Ok((true, None, ret, false, syscall_nr));
+                }
+                Err(e) => {
+                    eprintln!("Failed to read pathname: {}", e);
+                }
+            }
+        }
+        SYS_RENAMEAT => {
+            match (read_string(pid, regs.rsi), read_string(pid, regs.r10)) {
+                (Ok(src), Ok(dst)) => {
+                    println!("renameat: {} -> {}", src, dst);
+                    let result = fs.rename(&src, &dst).await;
+                    neutralize_syscall(pid, regs)?;
+                    let ret = match result {
+                        Ok(()) => 0,
+                        Err(e) => {
+                            eprintln!("Failed to rename {} to {}: {}", src, dst, e);
+                            -(libc::ENOENT as i64)
+                        }
+                    };
+                    return Ok((true, None, ret, false, syscall_nr));
+                }
+                _ => {
+                    eprintln!("Failed to read rename pathnames");
+                }
             }
         }
         _ => {}
     }
 
-    Ok((false, None, 0)) // Don't intercept
+    Ok((false, None, 0, false, syscall_nr)) // Don't intercept
 }
 
 async fn handle_syscall_exit(
     pid: Pid,
     fs: &mut WebSocketFileSystem,
-    should_intercept: bool,
-    pathname: Option<String>,
-    bytes_read: usize,
+    state: &SyscallState,
+    fd_inject_sock: Option<RawFd>,
 ) -> Result<(), PtraceError> {
-    if !should_intercept {
+    if !state.should_intercept {
         return Ok(());
     }
 
+    let table_id = state.table_id;
+    let pathname = state.pathname.clone();
+    let return_value = state.return_value;
+    let syscall_nr = state.syscall_nr;
+
     let mut regs = ptrace::getregs(pid)
         .map_err(|e| PtraceError::PtraceOperation(format!("getregs failed: {}", e)))?;
 
-    match regs.orig_rax as i64 {
+    // `orig_rax` may no longer name the syscall the entry handler saw: fake
+    // `read`/`write`/`lseek` neutralize it to `getpid` so the real kernel
+    // never sees a descriptor number it doesn't recognize. `syscall_nr` is
+    // what entry actually observed, and is what we dispatch on here.
+    match syscall_nr {
         SYS_OPENAT => {
-            println!("openat exit: {:?}", regs);
             if let Some(path) = pathname {
-                match fs.open_file(&path).await {
-                    Ok(fake_fd) => {}
+                let new_fd = match fd_inject_sock {
+                    // fs.open_file already cached `path` on disk in the
+                    // entry handler; open it here in the tracer and splice
+                    // the real fd into the tracee instead of fabricating one.
+                    Some(tracer_sock) => std::fs::File::open(&path)
+                        .map_err(|e| {
+                            PtraceError::PtraceOperation(format!(
+                                "Failed to open cached file {} for injection: {}",
+                                path, e
+                            ))
+                        })
+                        .and_then(|file| {
+                            fd_inject::inject_fd(
+                                pid,
+                                tracer_sock,
+                                FD_INJECT_SOCK_FD,
+                                file.as_raw_fd(),
+                            )
+                        }),
+                    None => {
+                        let fd = fs.allocate_fd();
+                        fs.register_fd(table_id, fd, &path);
+                        Ok(fd)
+                    }
+                };
+
+                match new_fd {
+                    Ok(fd) => {
+                        regs.rax = fd as u64;
+                    }
                     Err(e) => {
                         eprintln!("Failed to open file {}: {}", path, e);
-                        // Return ENOENT (2) to indicate file not found
-                        regs.rax = (-2_i64) as u64;
-                        ptrace::setregs(pid, regs).map_err(|e| {
-                            PtraceError::PtraceOperation(format!("setregs failed: {}", e))
-                        })?;
+                        regs.rax = (-2_i64) as u64; // ENOENT
                     }
                 }
+                ptrace::setregs(pid, regs)
+                    .map_err(|e| PtraceError::PtraceOperation(format!("setregs failed: {}", e)))?;
             }
         }
         SYS_READ => {
             // We already wrote the data in entry, just set the return value
-            regs.rax = bytes_read as u64;
+            regs.rax = return_value as u64;
             ptrace::setregs(pid, regs)
                 .map_err(|e| PtraceError::PtraceOperation(format!("setregs failed: {}", e)))?;
         }
         SYS_WRITE => {
             // We already handled the write in entry, just set the return value
-            regs.rax = bytes_read as u64; // bytes_read is repurposed as bytes_written here
+            regs.rax = return_value as u64; // repurposed as bytes_written here
             ptrace::setregs(pid, regs)
                 .map_err(|e| PtraceError::PtraceOperation(format!("setregs failed: {}", e)))?;
         }
         SYS_LSEEK => {
             // Return the new file position
-            regs.rax = bytes_read as u64; // bytes_read is repurposed as new_position here
+            regs.rax = return_value as u64; // repurposed as new_position here
             ptrace::setregs(pid, regs)
                 .map_err(|e| PtraceError::PtraceOperation(format!("setregs failed: {}", e)))?;
         }
@@ -247,103 +647,295 @@ async fn handle_syscall_exit(
             ptrace::setregs(pid, regs)
                 .map_err(|e| PtraceError::PtraceOperation(format!("setregs failed: {}", e)))?;
         }
+        SYS_NEWFSTATAT | SYS_GETDENTS64 | SYS_UNLINKAT | SYS_RENAMEAT => {
+            // Entry already ran the remote op (and, for stat/getdents64,
+            // wrote the result into the tracee's buffer); just surface the
+            // return value or negative errno it computed.
+            regs.rax = return_value as u64;
+            ptrace::setregs(pid, regs)
+                .map_err(|e| PtraceError::PtraceOperation(format!("setregs failed: {}", e)))?;
+        }
         _ => {}
     }
 
     Ok(())
 }
 
-async fn run_parent(pid: Pid) -> Result<i32, PtraceError> {
+/// Per-pid bookkeeping `run_parent` needs between a syscall's entry stop and
+/// its exit stop. Tracked per traced process since `fork`/`vfork`/`clone`/
+/// `exec`'d descendants are traced too and can have a syscall in flight of
+/// their own at any time.
+#[derive(Default)]
+struct SyscallState {
+    in_syscall: bool,
+    should_intercept: bool,
+    pathname: Option<String>,
+    return_value: i64,
+    syscall_nr: i64,
+    // The fake fd table this pid's in-flight syscall should use; copied from
+    // `table_ids` at entry so exit doesn't need it threaded through as its
+    // own parameter.
+    table_id: i32,
+    path_rewrite_scratch: Option<u64>,
+    // Set when this pid was just registered from a FORK/VFORK/CLONE event and
+    // hasn't had its first syscall-stop yet. Under classic (non-SEIZE) ptrace
+    // attachment the new child's own group-stop for the SIGSTOP that created
+    // it is still pending delivery, and it arrives here as an ordinary
+    // `WaitStatus::Stopped` independent of the parent's event stop. Swallow
+    // that one stopping signal instead of re-injecting it, or the child
+    // enters group-stop with nothing left to send it a SIGCONT.
+    awaiting_initial_stop: bool,
+}
+
+// Raw `PTRACE_EVENT_*` codes (see `linux/ptrace.h`); nix exposes these only
+// via the `Options` bits used to request them, not as a decoded enum on the
+// `PtraceEvent` wait status, so they're matched by number like the rest of
+// this file's hand-rolled kernel ABI constants.
+const PTRACE_EVENT_FORK: i32 = 1;
+const PTRACE_EVENT_VFORK: i32 = 2;
+const PTRACE_EVENT_CLONE: i32 = 3;
+const PTRACE_EVENT_EXEC: i32 = 4;
+
+/// Removes `exited_pid`'s entry from `table_ids` and, if no other live pid
+/// still shares its fd-table id (a `vfork()`/`clone()` sibling), drops the
+/// table itself so it doesn't linger for the rest of the trace.
+fn retire_fd_table(
+    table_ids: &mut HashMap<Pid, i32>,
+    fake_fs: &mut WebSocketFileSystem,
+    exited_pid: Pid,
+) {
+    let Some(table_id) = table_ids.remove(&exited_pid) else {
+        return;
+    };
+    if !table_ids.values().any(|id| *id == table_id) {
+        fake_fs.drop_fd_table(table_id);
+    }
+}
+
+async fn run_parent(pid: Pid, fd_inject_sock: Option<OwnedFd>) -> Result<i32, PtraceError> {
     let cache_dir = env::var("CACHE_DIR").unwrap_or_else(|_| "/tmp/cfc-cache".to_string());
-    let mut fake_fs = WebSocketFileSystem::new(cache_dir);
-    let mut in_syscall = false;
-    let mut should_intercept = false;
-    let mut pathname: Option<String> = None;
-    let mut bytes_read = 0;
+    let mut fake_fs = WebSocketFileSystem::from_env(cache_dir);
+    let fd_inject = fd_inject_sock.as_ref().map(|sock| sock.as_raw_fd());
+    // CFC_PATH_REWRITE=1 skips fd faking/injection entirely and instead
+    // points `openat` at the already-cached file so the kernel hands back a
+    // genuine fd; mutually exclusive with CFC_FD_INJECT.
+    let path_rewrite = env::var("CFC_PATH_REWRITE").as_deref() == Ok("1");
+
+    let mut states: HashMap<Pid, SyscallState> = HashMap::new();
+    states.insert(pid, SyscallState::default());
+    let mut live_pids: HashSet<Pid> = HashSet::new();
+    live_pids.insert(pid);
+
+    // Maps each traced pid to the id of the fake fd table it should use.
+    // `fork()` gives a child its own independent copy of the parent's fd
+    // table, so it gets a fresh id (keyed on its own pid); `vfork()`/
+    // `clone()` hand the child the very same fd table, so it reuses its
+    // parent's id instead. See `WebSocketFileSystem::clone_fd_table`.
+    let mut table_ids: HashMap<Pid, i32> = HashMap::new();
+    table_ids.insert(pid, pid.as_raw());
 
     // Start WebSocket server and wait for client
     println!("Starting WebSocket server...");
     fake_fs.start_server(8080).await.map_err(|e| {
         PtraceError::PtraceOperation(format!("Failed to start WebSocket server: {}", e))
     })?;
+    fake_fs.spawn_change_invalidator();
 
     wait().map_err(|e| PtraceError::PtraceOperation(format!("initial wait failed: {}", e)))?;
 
-    ptrace::setoptions(pid, ptrace::Options::PTRACE_O_TRACESYSGOOD)
-        .map_err(|e| PtraceError::PtraceOperation(format!("setoptions failed: {}", e)))?;
+    ptrace::setoptions(
+        pid,
+        ptrace::Options::PTRACE_O_TRACESYSGOOD
+            | ptrace::Options::PTRACE_O_TRACEFORK
+            | ptrace::Options::PTRACE_O_TRACEVFORK
+            | ptrace::Options::PTRACE_O_TRACECLONE
+            | ptrace::Options::PTRACE_O_TRACEEXEC,
+    )
+    .map_err(|e| PtraceError::PtraceOperation(format!("setoptions failed: {}", e)))?;
 
     ptrace::syscall(pid, None)
         .map_err(|e| PtraceError::PtraceOperation(format!("initial syscall failed: {}", e)))?;
 
     loop {
         match wait() {
-            Ok(WaitStatus::Stopped(_, Signal::SIGTRAP)) | Ok(WaitStatus::PtraceSyscall(_)) => {
-                if !in_syscall {
+            Ok(WaitStatus::Stopped(stopped_pid, Signal::SIGTRAP))
+            | Ok(WaitStatus::PtraceSyscall(stopped_pid)) => {
+                let mut already_resumed = false;
+                let table_id = *table_ids.get(&stopped_pid).unwrap_or(&stopped_pid.as_raw());
+                let state = states.entry(stopped_pid).or_default();
+                state.awaiting_initial_stop = false;
+
+                if !state.in_syscall {
                     // Syscall entry
-                    match handle_syscall_entry(pid, &mut fake_fs).await {
-                        Ok((intercept, path, read_bytes)) => {
-                            should_intercept = intercept;
-                            pathname = path;
-                            bytes_read = read_bytes;
-                            in_syscall = true;
+                    let scratch = if path_rewrite {
+                        Some(&mut state.path_rewrite_scratch)
+                    } else {
+                        None
+                    };
+                    match handle_syscall_entry(stopped_pid, table_id, &mut fake_fs, scratch).await {
+                        Ok((intercept, path, ret, resumed, nr)) => {
+                            state.should_intercept = intercept;
+                            state.pathname = path;
+                            state.return_value = ret;
+                            already_resumed = resumed;
+                            state.in_syscall = !resumed;
+                            state.syscall_nr = nr;
+                            state.table_id = table_id;
                         }
                         Err(e) => {
-                            println!("Error handling syscall entry: {}", e);
+                            println!("Error handling syscall entry for pid {}: {}", stopped_pid, e);
                         }
                     }
                 } else {
                     // Syscall exit
-                    if let Err(e) = handle_syscall_exit(
-                        pid,
-                        &mut fake_fs,
-                        should_intercept,
-                        pathname.clone(),
-                        bytes_read,
-                    )
-                    .await
+                    if let Err(e) =
+                        handle_syscall_exit(stopped_pid, &mut fake_fs, state, fd_inject).await
                     {
-                        println!("Error handling syscall exit: {}", e);
+                        println!("Error handling syscall exit for pid {}: {}", stopped_pid, e);
                     }
-                    in_syscall = false;
-                    should_intercept = false;
-                    pathname = None;
-                    bytes_read = 0;
+                    state.in_syscall = false;
+                    state.should_intercept = false;
+                    state.pathname = None;
+                    state.return_value = 0;
+                    state.syscall_nr = 0;
                 }
 
-                ptrace::syscall(pid, None).map_err(|e| {
+                // When path rewriting already ran the real `openat` to
+                // completion and resumed the tracee itself, there's no
+                // matching stop left for us to continue past here.
+                if !already_resumed {
+                    ptrace::syscall(stopped_pid, None).map_err(|e| {
+                        PtraceError::PtraceOperation(format!("syscall continue failed: {}", e))
+                    })?;
+                }
+            }
+            Ok(WaitStatus::PtraceEvent(event_pid, _signal, event)) => {
+                if matches!(
+                    event,
+                    PTRACE_EVENT_FORK | PTRACE_EVENT_VFORK | PTRACE_EVENT_CLONE
+                ) {
+                    let new_pid = ptrace::getevent(event_pid).map_err(|e| {
+                        PtraceError::PtraceOperation(format!("getevent failed: {}", e))
+                    })?;
+                    let child = Pid::from_raw(new_pid as i32);
+                    println!("Traced new child pid {} of {}", child, event_pid);
+                    // The kernel doesn't guarantee this event stop is
+                    // observed before the child's own initial stopping-signal
+                    // delivery; if that stop already raced in ahead of us (see
+                    // the `WaitStatus::Stopped` arm below), don't clobber the
+                    // entry it already created and may have consumed.
+                    states.entry(child).or_insert_with(|| SyscallState {
+                        awaiting_initial_stop: true,
+                        ..Default::default()
+                    });
+                    live_pids.insert(child);
+
+                    let parent_table =
+                        *table_ids.get(&event_pid).unwrap_or(&event_pid.as_raw());
+                    if event == PTRACE_EVENT_FORK {
+                        // `fork()` hands the child its own independent copy
+                        // of the fd table, so give it a fresh id and seed it
+                        // from the parent's currently-open fds.
+                        table_ids.insert(child, child.as_raw());
+                        fake_fs.clone_fd_table(parent_table, child.as_raw());
+                    } else {
+                        // `vfork()`/`clone()` share the real fd table with
+                        // the parent, so the child just reuses its id.
+                        table_ids.insert(child, parent_table);
+                    }
+                } else if event == PTRACE_EVENT_EXEC {
+                    println!("pid {} exec'd", event_pid);
+                }
+
+                // This event stop replaces what would otherwise have been
+                // the fork/vfork/clone/exec syscall's own exit stop, so
+                // there's no separate exit to pair up for `event_pid`.
+                if let Some(state) = states.get_mut(&event_pid) {
+                    state.in_syscall = false;
+                    state.should_intercept = false;
+                    state.pathname = None;
+                    state.return_value = 0;
+                    state.syscall_nr = 0;
+                }
+
+                ptrace::syscall(event_pid, None).map_err(|e| {
                     PtraceError::PtraceOperation(format!("syscall continue failed: {}", e))
                 })?;
             }
-            Ok(WaitStatus::Stopped(_, signal)) => {
-                if signal != Signal::SIGURG {
-                    println!("Process stopped by signal {:?}, continuing...", signal);
+            Ok(WaitStatus::Stopped(stopped_pid, signal)) => {
+                // A freshly traced fork/vfork/clone child's own stop for the
+                // SIGSTOP (or other stopping signal) that attached it is still
+                // outstanding here; re-injecting it would put the child into
+                // group-stop with nothing left to send it a SIGCONT. Swallow
+                // it exactly once instead of forwarding it.
+                //
+                // The kernel gives no ordering guarantee between this stop
+                // and the parent's `PTRACE_EVENT_FORK`/`VFORK`/`CLONE` stop
+                // that normally creates `stopped_pid`'s entry first, so a
+                // pid we've never seen here is itself proof this is that
+                // initial stop racing in ahead of the event: every pid we
+                // `wait()` on is either the root tracee (already present in
+                // `states`) or one of its traced descendants, so there is no
+                // other way for a brand new pid to show up here.
+                let is_stopping_signal = matches!(
+                    signal,
+                    Signal::SIGSTOP | Signal::SIGTSTP | Signal::SIGTTIN | Signal::SIGTTOU
+                );
+                let state = states.entry(stopped_pid).or_insert_with(|| SyscallState {
+                    awaiting_initial_stop: true,
+                    ..Default::default()
+                });
+                let swallow = is_stopping_signal && state.awaiting_initial_stop;
+
+                if swallow {
+                    state.awaiting_initial_stop = false;
+                    ptrace::syscall(stopped_pid, None).map_err(|e| {
+                        PtraceError::PtraceOperation(format!("syscall continue failed: {}", e))
+                    })?;
+                } else {
+                    if signal != Signal::SIGURG {
+                        println!(
+                            "Process {} stopped by signal {:?}, continuing...",
+                            stopped_pid, signal
+                        );
+                    }
+                    ptrace::syscall(stopped_pid, Some(signal)).map_err(|e| {
+                        PtraceError::PtraceOperation(format!("syscall with signal failed: {}", e))
+                    })?;
                 }
-                ptrace::syscall(pid, Some(signal)).map_err(|e| {
-                    PtraceError::PtraceOperation(format!("syscall with signal failed: {}", e))
-                })?;
             }
-            Ok(WaitStatus::Exited(_, exit_status)) => {
-                println!("Process exited with status {}", exit_status);
-                return Ok(exit_status);
+            Ok(WaitStatus::Exited(exited_pid, exit_status)) => {
+                println!("Process {} exited with status {}", exited_pid, exit_status);
+                states.remove(&exited_pid);
+                live_pids.remove(&exited_pid);
+                retire_fd_table(&mut table_ids, &mut fake_fs, exited_pid);
+                if live_pids.is_empty() {
+                    return Ok(exit_status);
+                }
             }
-            Ok(WaitStatus::Signaled(_, signal, _)) => {
-                println!("Process killed by signal {:?}", signal);
-                return Ok(128 + signal as i32);  // Standard convention for signal termination
+            Ok(WaitStatus::Signaled(signaled_pid, signal, _)) => {
+                println!("Process {} killed by signal {:?}", signaled_pid, signal);
+                states.remove(&signaled_pid);
+                live_pids.remove(&signaled_pid);
+                retire_fd_table(&mut table_ids, &mut fake_fs, signaled_pid);
+                if live_pids.is_empty() {
+                    return Ok(128 + signal as i32); // Standard convention for signal termination
+                }
             }
             Ok(status) => {
                 println!("Other status: {:?}", status);
-                ptrace::syscall(pid, None).map_err(|e| {
-                    PtraceError::PtraceOperation(format!("syscall continue failed: {}", e))
-                })?;
+                if let Some(stopped_pid) = status.pid() {
+                    ptrace::syscall(stopped_pid, None).map_err(|e| {
+                        PtraceError::PtraceOperation(format!("syscall continue failed: {}", e))
+                    })?;
+                }
             }
             Err(err) => {
                 eprintln!("Wait error: {}", err);
-                return Ok(1);  // Return error exit code
+                return Ok(1); // Return error exit code
             }
         }
     }
-
-    Ok(0)  // Should not reach here, but return success if we do
 }
 
 fn main() {
@@ -359,15 +951,51 @@ fn main() {
 
     let rt = Runtime::new().expect("Failed to create tokio runtime");
 
+    // CFC_BACKEND=seccomp trades the ptrace loop's double-stop-per-syscall
+    // overhead for a seccomp user-notification filter that only traps the
+    // syscalls we actually handle. Ptrace remains the default since it needs
+    // no kernel feature beyond ptrace itself.
+    let backend = env::var("CFC_BACKEND").unwrap_or_else(|_| "ptrace".to_string());
+
+    if backend == "seccomp" {
+        match rt.block_on(seccomp_backend::run(program, program_args)) {
+            Ok(exit_code) => exit(exit_code),
+            Err(e) => {
+                eprintln!("Seccomp backend error: {}", e);
+                exit(1);
+            }
+        }
+    }
+
+    // CFC_FD_INJECT=1 additionally splices a real fd for the cached file
+    // into the tracee on `openat` instead of handing back a fake one that
+    // every later `read`/`write`/`lseek`/`close` has to keep emulating.
+    let fd_inject = env::var("CFC_FD_INJECT").as_deref() == Ok("1");
+    let fd_inject_socks = if fd_inject {
+        Some(
+            socketpair(
+                AddressFamily::Unix,
+                SockType::Datagram,
+                None,
+                SockFlag::empty(),
+            )
+            .expect("Failed to create fd-injection socketpair"),
+        )
+    } else {
+        None
+    };
+
     match unsafe { fork() } {
         Ok(ForkResult::Child) => {
-            if let Err(e) = run_child(program, program_args) {
+            let child_sock = fd_inject_socks.map(|(_, child_sock)| child_sock);
+            if let Err(e) = run_child(program, program_args, child_sock) {
                 eprintln!("Child process error: {}", e);
                 exit(1);
             }
         }
         Ok(ForkResult::Parent { child }) => {
-            match rt.block_on(run_parent(child)) {
+            let tracer_sock = fd_inject_socks.map(|(tracer_sock, _)| tracer_sock);
+            match rt.block_on(run_parent(child, tracer_sock)) {
                 Ok(exit_code) => {
                     exit(exit_code);
                 }
@@ -0,0 +1,139 @@
+//! Path-rewriting for the ptrace backend: a lighter-weight alternative to fd
+//! faking and to real-fd injection (see `fd_inject`). Instead of handing the
+//! tracee a descriptor after the fact, this rewrites `openat`'s pathname
+//! argument *before* the real syscall runs, pointing it at the local cache
+//! file `WebSocketFileSystem` has already materialized at the same path.
+//! The kernel then does the actual `open` itself, so every later
+//! `read`/`write`/`lseek`/`close` against the resulting fd needs no
+//! exit-stage handling at all.
+//!
+//! The tracee has no spare writable memory we can address by construction,
+//! so the first rewrite on a given pid lazily injects an anonymous `mmap` to
+//! get one, the same register-rewriting trick `fd_inject` uses to inject
+//! `recvmsg`. Every later `openat` on that pid just reuses the scratch page.
+
+use nix::sys::ptrace;
+use nix::sys::signal::Signal;
+use nix::sys::wait::{waitpid, WaitStatus};
+use nix::unistd::Pid;
+
+use crate::{PtraceError, MAX_STRING_LENGTH, PAGE_SIZE};
+
+const SYS_MMAP: u64 = 9;
+
+/// Points the `openat` the tracee is currently entering at `local_path`
+/// instead of its original pathname argument, allocating a scratch page in
+/// the tracee's address space on first use.
+///
+/// Returns `true` if the caller's normal entry/exit pairing still applies
+/// (the cheap, already-allocated case) or `false` if the real `openat` was
+/// already run and resumed internally, meaning there is no matching exit
+/// stop left for `run_parent` to drive — it should go straight back to
+/// waiting for the next syscall instead.
+pub fn rewrite_openat_path(
+    pid: Pid,
+    scratch_addr: &mut Option<u64>,
+    local_path: &str,
+) -> Result<bool, PtraceError> {
+    match *scratch_addr {
+        Some(addr) => {
+            write_path(pid, addr, local_path)?;
+            let mut regs = ptrace::getregs(pid)
+                .map_err(|e| PtraceError::PtraceOperation(format!("getregs failed: {}", e)))?;
+            regs.rsi = addr;
+            ptrace::setregs(pid, regs)
+                .map_err(|e| PtraceError::PtraceOperation(format!("setregs failed: {}", e)))?;
+            Ok(true)
+        }
+        None => {
+            let addr = allocate_scratch_and_run(pid, local_path)?;
+            *scratch_addr = Some(addr);
+            Ok(false)
+        }
+    }
+}
+
+fn write_path(pid: Pid, addr: u64, local_path: &str) -> Result<(), PtraceError> {
+    let mut bytes = local_path.as_bytes().to_vec();
+    bytes.truncate(MAX_STRING_LENGTH - 1);
+    bytes.push(0);
+    crate::write_data_to_child(pid, addr, &bytes)
+}
+
+/// First-use path: we're sitting at `openat`'s entry stop with no scratch
+/// page yet, so that stop gets spent substituting an anonymous `mmap`
+/// instead. Once we have an address, the `openat` the tracee actually
+/// wanted to run is re-injected via the same rewind-`rip`-and-resume trick
+/// `fd_inject` uses, this time pointed at the rewritten path, and let run to
+/// real completion (a genuine fd lands in `rax`). The tracee is left
+/// resumed past that point — nothing more to rewrite.
+fn allocate_scratch_and_run(pid: Pid, local_path: &str) -> Result<u64, PtraceError> {
+    let openat_regs = ptrace::getregs(pid)
+        .map_err(|e| PtraceError::PtraceOperation(format!("getregs failed: {}", e)))?;
+
+    let mut mmap_regs = openat_regs;
+    mmap_regs.orig_rax = SYS_MMAP;
+    mmap_regs.rax = SYS_MMAP;
+    mmap_regs.rdi = 0; // let the kernel pick the address
+    mmap_regs.rsi = PAGE_SIZE as u64;
+    mmap_regs.rdx = (libc::PROT_READ | libc::PROT_WRITE) as u64;
+    mmap_regs.r10 = (libc::MAP_PRIVATE | libc::MAP_ANONYMOUS) as u64;
+    mmap_regs.r8 = (-1_i64) as u64; // fd
+    mmap_regs.r9 = 0; // offset
+    ptrace::setregs(pid, mmap_regs)
+        .map_err(|e| PtraceError::PtraceOperation(format!("setregs failed: {}", e)))?;
+
+    step_to_next_stop(pid)?; // substituted mmap's exit stop
+
+    let mmap_result = ptrace::getregs(pid)
+        .map_err(|e| PtraceError::PtraceOperation(format!("getregs failed: {}", e)))?;
+    let addr = mmap_result.rax;
+    if (addr as i64) < 0 {
+        return Err(PtraceError::PtraceOperation(format!(
+            "injected mmap failed: errno {}",
+            -(addr as i64)
+        )));
+    }
+
+    write_path(pid, addr, local_path)?;
+
+    let mut regs = openat_regs;
+    regs.rsi = addr;
+    // At the entry stop `rax` held -ENOSYS, not the syscall number; the
+    // re-executed `syscall` instruction reads its number from `rax`.
+    regs.rax = openat_regs.orig_rax;
+    // Same rewind as `fd_inject::recvmsg_in_tracee`: rip sits right after the
+    // 2-byte `syscall` instruction, so stepping it back re-executes a
+    // syscall with these registers instead of whatever comes next.
+    regs.rip = openat_regs.rip - 2;
+    ptrace::setregs(pid, regs)
+        .map_err(|e| PtraceError::PtraceOperation(format!("setregs failed: {}", e)))?;
+
+    step_to_next_stop(pid)?; // reinjected openat's entry stop
+    step_to_next_stop(pid)?; // reinjected openat's exit stop, real fd in rax
+
+    // The real `openat` already ran to completion with the rewritten path;
+    // `rax` holds exactly what the tracee should see, so just let it go.
+    ptrace::syscall(pid, None)
+        .map_err(|e| PtraceError::PtraceOperation(format!("syscall continue failed: {}", e)))?;
+
+    Ok(addr)
+}
+
+fn step_to_next_stop(pid: Pid) -> Result<(), PtraceError> {
+    ptrace::syscall(pid, None)
+        .map_err(|e| PtraceError::PtraceOperation(format!("syscall step failed: {}", e)))?;
+
+    // Wait specifically on `pid`, not the untargeted `waitpid(-1, ...)` a
+    // bare `wait()` performs: with other tracees live (forked/cloned
+    // descendants under `PTRACE_O_TRACEFORK` et al.), an untargeted wait
+    // could reap a different pid's stop mid-rewrite and never resume it.
+    match waitpid(pid, None) {
+        Ok(WaitStatus::Stopped(_, Signal::SIGTRAP)) | Ok(WaitStatus::PtraceSyscall(_)) => Ok(()),
+        Ok(status) => Err(PtraceError::PtraceOperation(format!(
+            "unexpected wait status during path rewrite: {:?}",
+            status
+        ))),
+        Err(e) => Err(PtraceError::PtraceOperation(format!("wait failed: {}", e))),
+    }
+}
@@ -1,13 +1,54 @@
+use chacha20poly1305::{
+    aead::{Aead, AeadCore, KeyInit, OsRng},
+    ChaCha20Poly1305, Key, Nonce,
+};
 use futures_util::{SinkExt, StreamExt};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::env;
+use std::io::Write as _;
 use std::path::Path;
-use std::sync::Arc;
-use tokio::net::TcpListener;
-use tokio::sync::{mpsc, Mutex};
-use tokio_tungstenite::{accept_async, tungstenite::Message};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex as StdMutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::{broadcast, mpsc, oneshot, Mutex, Semaphore};
+use tokio_tungstenite::{accept_async, tungstenite::Message, WebSocketStream};
 use uuid::Uuid;
 
+/// Default size of each chunked `Read` request issued while filling the cache.
+const DEFAULT_CHUNK_SIZE: usize = 256 * 1024;
+/// Default time to wait for a response to an outstanding request before
+/// giving up and failing it with `FileError::Timeout`.
+const DEFAULT_REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+/// Name of the sled tree directory nested inside the cache directory.
+const METADATA_DB_DIR: &str = ".cache-metadata";
+/// Backlog of change notifications kept for a subscriber that isn't polling
+/// as fast as the remote is pushing them.
+const CHANGE_EVENT_CAPACITY: usize = 256;
+/// Length in bytes of the random nonce stored ahead of the ciphertext in each
+/// sealed chunk of an encrypted cache file.
+const NONCE_LEN: usize = 12;
+/// Length in bytes of the little-endian ciphertext-length prefix ahead of
+/// each sealed chunk's nonce, so `open_chunks` knows where one chunk's
+/// ciphertext ends and the next chunk's frame begins.
+const FRAME_LEN_PREFIX: usize = 4;
+/// Default cap on how many WebSocket clients may be connected at once.
+const DEFAULT_MAX_CONNECTIONS: usize = 16;
+/// First fd handed out by `allocate_fd`, chosen well clear of the handful of
+/// low-numbered fds a typical tracee already has open.
+const FIRST_FAKE_FD: i32 = 1000;
+
+/// Maps an in-flight request id to the connection it was sent on and the
+/// oneshot that resolves `dispatch`'s caller once a response (or a dropped
+/// connection) settles it.
+// The `FSRequest` is kept alongside the connection it went out on (not just
+// the oneshot the caller is awaiting) so a dead connection's in-flight
+// requests can be replayed onto another client instead of just failing; see
+// `replay_pending`.
+type PendingRequests =
+    Arc<Mutex<HashMap<String, (u64, FSRequest, oneshot::Sender<FSResponseWithBinary>)>>>;
+
 #[derive(Debug)]
 pub enum FileError {
     WebSocketRequest(Box<dyn std::error::Error>),
@@ -17,6 +58,12 @@ pub enum FileError {
     CacheWriteFailed(std::io::Error),
     CacheReadFailed(std::io::Error),
     RemoteError(String),
+    Timeout(String),
+    CacheIndexFailed(sled::Error),
+    /// A sealed cache file failed to decrypt or its AEAD tag didn't verify.
+    /// Treated as a cache miss, not a hard error, so a corrupted or tampered
+    /// cache file self-heals via a fresh remote read.
+    DecryptionFailure,
 }
 
 impl std::fmt::Display for FileError {
@@ -31,18 +78,26 @@ impl std::fmt::Display for FileError {
             FileError::CacheWriteFailed(e) => write!(f, "Failed to write to cache: {}", e),
             FileError::CacheReadFailed(e) => write!(f, "Failed to read from cache: {}", e),
             FileError::RemoteError(msg) => write!(f, "Remote server error: {}", msg),
+            FileError::Timeout(request_id) => {
+                write!(f, "Request {} timed out waiting for a response", request_id)
+            }
+            FileError::CacheIndexFailed(e) => write!(f, "Cache metadata index error: {}", e),
+            FileError::DecryptionFailure => {
+                write!(f, "Cache file failed to decrypt or verify")
+            }
         }
     }
 }
 
 impl std::error::Error for FileError {}
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(tag = "operation")]
 pub enum FSRequest {
     #[serde(rename = "read")]
     Read {
         id: String,
+        seq: u64,
         path: String,
         size: usize,
         offset: usize,
@@ -50,10 +105,107 @@ pub enum FSRequest {
     #[serde(rename = "write")]
     Write {
         id: String,
+        seq: u64,
         path: String,
         offset: usize,
         data: Vec<u8>,
     },
+    /// Lightweight existence/freshness check used to revalidate a cached
+    /// file without re-downloading it: the remote echoes back its current
+    /// size/mtime/hash so we can compare against what's in the cache index.
+    #[serde(rename = "stat")]
+    Stat { id: String, seq: u64, path: String },
+    /// Registers interest in change notifications for `path`, which may name
+    /// an exact file or a directory prefix. The remote acks this like any
+    /// other request, then later pushes unsolicited `FSResponse`s carrying
+    /// `path`/`kind` instead of an `id` that matches a pending request.
+    #[serde(rename = "watch")]
+    Watch { id: String, seq: u64, path: String },
+    /// Full metadata for `path` (size/mtime/file type), returned in
+    /// `FSResponse::metadata`. Unlike `Stat`, this isn't limited to the
+    /// fields needed to revalidate a cache entry.
+    #[serde(rename = "metadata")]
+    Metadata { id: String, seq: u64, path: String },
+    /// Lists the immediate children of directory `path`, returned in
+    /// `FSResponse::entries`.
+    #[serde(rename = "read_dir")]
+    ReadDir { id: String, seq: u64, path: String },
+    /// Creates an empty file at `path`, failing if it already exists.
+    #[serde(rename = "create")]
+    Create { id: String, seq: u64, path: String },
+    /// Removes `path`. `recursive` allows removing a non-empty directory.
+    #[serde(rename = "remove")]
+    Remove {
+        id: String,
+        seq: u64,
+        path: String,
+        recursive: bool,
+    },
+    /// Moves/renames `src` to `dst`.
+    #[serde(rename = "rename")]
+    Rename {
+        id: String,
+        seq: u64,
+        src: String,
+        dst: String,
+    },
+    /// Copies `src` to `dst`, leaving `src` in place.
+    #[serde(rename = "copy")]
+    Copy {
+        id: String,
+        seq: u64,
+        src: String,
+        dst: String,
+    },
+    /// Truncates (or extends with zeros) `path` to exactly `size` bytes.
+    #[serde(rename = "truncate")]
+    Truncate {
+        id: String,
+        seq: u64,
+        path: String,
+        size: u64,
+    },
+}
+
+/// The kind of filesystem entry a `DirEntry` or `FileMetadata` describes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FileType {
+    File,
+    Dir,
+    Symlink,
+}
+
+/// A single entry returned by a `ReadDir` request.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DirEntry {
+    pub name: String,
+    pub file_type: FileType,
+    pub path: String,
+}
+
+/// Full metadata for a remote path, returned by a `Metadata` request.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FileMetadata {
+    pub size: u64,
+    pub mtime: Option<i64>,
+    pub file_type: FileType,
+}
+
+/// The kind of change a remote-pushed `ChangeEvent` reports.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChangeKind {
+    Created,
+    Modified,
+    Removed,
+}
+
+/// An unsolicited notification that a watched remote path changed.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChangeEvent {
+    pub path: String,
+    pub kind: ChangeKind,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -65,6 +217,21 @@ pub struct FSResponse {
     pub bytes_written: Option<usize>,
     pub position: Option<i64>,
     pub error: Option<String>,
+    /// Remote file size in bytes, returned by `Read`/`Stat` responses.
+    pub size: Option<u64>,
+    /// Remote modification time (unix seconds), if the remote tracks one.
+    pub mtime: Option<i64>,
+    /// Remote content hash, if the remote can cheaply compute one.
+    pub hash: Option<String>,
+    /// Present (with `kind`) on unsolicited change-notification frames,
+    /// which don't match any pending request id.
+    pub path: Option<String>,
+    /// Present alongside `path` on change-notification frames.
+    pub kind: Option<ChangeKind>,
+    /// Populated by `Metadata` responses.
+    pub metadata: Option<FileMetadata>,
+    /// Populated by `ReadDir` responses.
+    pub entries: Option<Vec<DirEntry>>,
 }
 
 pub struct FSResponseWithBinary {
@@ -78,22 +245,658 @@ pub struct CachedFile {
     pub path: String,
 }
 
+/// Metadata tracked per cached remote file, persisted in the sled index so a
+/// cache hit means more than "a file happens to exist at this path".
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct CacheMetadata {
+    size: u64,
+    remote_hash: Option<String>,
+    remote_mtime: Option<i64>,
+    cached_at: u64,
+    expires_at: Option<u64>,
+}
+
 pub struct WebSocketFileSystem {
-    ws_sender: Option<mpsc::UnboundedSender<Message>>,
-    open_files: HashMap<i32, CachedFile>,
-    pending_requests:
-        Arc<Mutex<HashMap<String, tokio::sync::oneshot::Sender<FSResponseWithBinary>>>>,
+    /// Outgoing message sender for each currently connected client, keyed by
+    /// connection id.
+    connections: Arc<Mutex<HashMap<u64, mpsc::UnboundedSender<Message>>>>,
+    /// Generates unique connection ids for `accept_loop`.
+    next_connection_id: Arc<AtomicU64>,
+    /// Round-robin cursor used to spread outgoing requests across whichever
+    /// connections are currently live.
+    next_connection_index: Arc<AtomicU64>,
+    /// Bounds how many clients may be connected at once.
+    connection_semaphore: Arc<Semaphore>,
+    /// Fake fd tables, keyed by fd-table id. A real fd table is per-process,
+    /// so each top-level traced process and each `fork()`ed descendant (an
+    /// independent copy of its parent's table from the moment of the fork)
+    /// gets its own id; a `vfork()`/`clone()`ed descendant instead reuses its
+    /// parent's id, since those calls hand the child the very same fd table
+    /// rather than a copy. The caller (`run_parent`'s per-pid dispatch) owns
+    /// the pid-to-table-id mapping and picks the id to key these calls on;
+    /// see `clone_fd_table`.
+    open_files: HashMap<i32, HashMap<i32, CachedFile>>,
+    next_fd: i32,
+    pending_requests: PendingRequests,
+    chunk_size: usize,
+    next_seq: Arc<AtomicU64>,
+    request_timeout: Duration,
+    metadata_db: sled::Db,
+    default_ttl: Option<Duration>,
+    change_tx: broadcast::Sender<ChangeEvent>,
+    /// Ref-counts how many currently-open fake fds (plus any explicit
+    /// `watch` callers) are backed by each path. Shared with the background
+    /// task `spawn_change_invalidator` starts, so a remote push notification
+    /// can't evict a path's cache file out from under an fd a traced process
+    /// still has open against it.
+    watched_paths: Arc<StdMutex<HashMap<String, usize>>>,
+    /// When set, every cache write is sealed with this cipher (see
+    /// `with_encryption`) and every cache read is verified against it.
+    cipher: Option<ChaCha20Poly1305>,
 }
 
 impl WebSocketFileSystem {
+    /// Builds a `WebSocketFileSystem` rooted at `cache_dir` the way both
+    /// backends' `run_parent` do: applying the optional `CFC_CACHE_TTL_SECS`,
+    /// `CFC_CACHE_KEY`, `CFC_CHUNK_SIZE`, and `CFC_REQUEST_TIMEOUT_SECS`
+    /// environment variables on top of `new`'s defaults, so the ptrace and
+    /// seccomp backends don't each duplicate this env-var plumbing.
+    pub fn from_env(cache_dir: String) -> Self {
+        let mut fs = Self::new(cache_dir);
+
+        if let Ok(ttl_secs) = env::var("CFC_CACHE_TTL_SECS") {
+            match ttl_secs.parse::<u64>() {
+                Ok(secs) => fs = fs.with_cache_ttl(Duration::from_secs(secs)),
+                Err(e) => eprintln!("Ignoring invalid CFC_CACHE_TTL_SECS: {}", e),
+            }
+        }
+
+        if let Ok(hex_key) = env::var("CFC_CACHE_KEY") {
+            match Self::parse_cache_key(&hex_key) {
+                Some(key) => fs = fs.with_encryption(key),
+                None => eprintln!("Ignoring invalid CFC_CACHE_KEY: expected 64 hex characters"),
+            }
+        }
+
+        if let Ok(chunk_size) = env::var("CFC_CHUNK_SIZE") {
+            match chunk_size.parse::<usize>() {
+                Ok(size) => fs = fs.with_chunk_size(size),
+                Err(e) => eprintln!("Ignoring invalid CFC_CHUNK_SIZE: {}", e),
+            }
+        }
+
+        if let Ok(timeout_secs) = env::var("CFC_REQUEST_TIMEOUT_SECS") {
+            match timeout_secs.parse::<u64>() {
+                Ok(secs) => fs = fs.with_request_timeout(Duration::from_secs(secs)),
+                Err(e) => eprintln!("Ignoring invalid CFC_REQUEST_TIMEOUT_SECS: {}", e),
+            }
+        }
+
+        fs
+    }
+
     pub fn new(cache_dir: String) -> Self {
         std::fs::create_dir_all(&cache_dir).expect("Failed to create cache directory");
 
+        let metadata_db = sled::open(Path::new(&cache_dir).join(METADATA_DB_DIR))
+            .expect("Failed to open cache metadata index");
+
+        let (change_tx, _) = broadcast::channel(CHANGE_EVENT_CAPACITY);
+
         Self {
-            ws_sender: None,
+            connections: Arc::new(Mutex::new(HashMap::new())),
+            next_connection_id: Arc::new(AtomicU64::new(0)),
+            next_connection_index: Arc::new(AtomicU64::new(0)),
+            connection_semaphore: Arc::new(Semaphore::new(DEFAULT_MAX_CONNECTIONS)),
             open_files: HashMap::new(),
+            next_fd: FIRST_FAKE_FD,
             pending_requests: Arc::new(Mutex::new(HashMap::new())),
+            chunk_size: DEFAULT_CHUNK_SIZE,
+            metadata_db,
+            default_ttl: None,
+            next_seq: Arc::new(AtomicU64::new(0)),
+            request_timeout: DEFAULT_REQUEST_TIMEOUT,
+            change_tx,
+            watched_paths: Arc::new(StdMutex::new(HashMap::new())),
+            cipher: None,
+        }
+    }
+
+    /// Subscribe to unsolicited remote change notifications pushed for any
+    /// path this filesystem has `watch`ed.
+    pub fn subscribe_changes(&self) -> broadcast::Receiver<ChangeEvent> {
+        self.change_tx.subscribe()
+    }
+
+    /// Spawns a background task that evicts the local cache entry for every
+    /// `ChangeEvent` the remote pushes for a watched path, so a traced
+    /// process's next `openat` re-downloads instead of serving bytes the
+    /// remote has already moved past. `metadata_db` is a cheap `sled::Db`
+    /// handle clone, so the task outlives any borrow of `self`.
+    pub fn spawn_change_invalidator(&self) {
+        let mut changes = self.subscribe_changes();
+        let metadata_db = self.metadata_db.clone();
+        let watched_paths = self.watched_paths.clone();
+
+        tokio::spawn(async move {
+            while let Ok(event) = changes.recv().await {
+                // A path with a live ref count has a fake fd open against it
+                // right now; deleting its cache file out from under that fd
+                // would turn every further `pread`/`pwrite` on it into a
+                // silent, corrupting cache miss. Leave it on disk and let the
+                // fd's owner pick up the change on its next open instead.
+                let in_use = watched_paths
+                    .lock()
+                    .unwrap()
+                    .get(&event.path)
+                    .is_some_and(|count| *count > 0);
+                if in_use {
+                    println!(
+                        "Deferring cache invalidation for {}: still open",
+                        event.path
+                    );
+                    // Dropping only the index entry keeps the file for the
+                    // open fd but makes the next `open_file` re-download.
+                    if let Err(e) = metadata_db.remove(event.path.as_bytes()) {
+                        eprintln!(
+                            "Failed to invalidate cache metadata for {}: {}",
+                            event.path, e
+                        );
+                    }
+                    continue;
+                }
+
+                println!("Invalidating cache for remotely-changed path: {}", event.path);
+                if let Err(e) = metadata_db.remove(event.path.as_bytes()) {
+                    eprintln!(
+                        "Failed to invalidate cache metadata for {}: {}",
+                        event.path, e
+                    );
+                }
+                let _ = std::fs::remove_file(&event.path);
+            }
+        });
+    }
+
+    /// Registers interest in change notifications for `path`, an exact file
+    /// path (every call site watches the file it just opened, never a
+    /// directory). Ref-counted, so multiple open files under the same
+    /// watched path share a single remote watch; see `unwatch`.
+    ///
+    /// `spawn_change_invalidator`'s in-use guard does an exact-string lookup
+    /// against `watched_paths`, so a directory-prefix watch would not be
+    /// correctly ref-counted against files opened under it — don't register
+    /// one without teaching the invalidator prefix matching first.
+    pub async fn watch(&mut self, path: &str) -> Result<(), FileError> {
+        let is_first_watcher = {
+            let mut watched = self.watched_paths.lock().unwrap();
+            let count = watched.entry(path.to_string()).or_insert(0);
+            *count += 1;
+            *count == 1
+        };
+
+        if !is_first_watcher {
+            return Ok(());
+        }
+
+        let watch_request = FSRequest::Watch {
+            id: Uuid::new_v4().to_string(),
+            seq: self.next_seq(),
+            path: path.to_string(),
+        };
+
+        let response = self
+            .send_request(watch_request)
+            .await
+            .map_err(FileError::WebSocketRequest)?;
+
+        if !response.response.success {
+            if let Some(count) = self.watched_paths.lock().unwrap().get_mut(path) {
+                *count -= 1;
+            }
+            let error_msg = response
+                .response
+                .error
+                .unwrap_or_else(|| "Unknown watch error".to_string());
+            return Err(FileError::RemoteError(error_msg));
+        }
+
+        Ok(())
+    }
+
+    /// Drops interest in `path` previously registered with `watch`. A no-op
+    /// if `path` wasn't being watched.
+    pub fn unwatch(&mut self, path: &str) {
+        let mut watched = self.watched_paths.lock().unwrap();
+        if let Some(count) = watched.get_mut(path) {
+            if *count <= 1 {
+                watched.remove(path);
+            } else {
+                *count -= 1;
+            }
+        }
+    }
+
+    /// Override the chunk size used to fill the local cache (mainly for tests).
+    pub fn with_chunk_size(mut self, chunk_size: usize) -> Self {
+        self.chunk_size = chunk_size;
+        self
+    }
+
+    /// Override how long `send_request`/`send_request_with_binary` wait for a
+    /// response before failing the request with `FileError::Timeout`.
+    pub fn with_request_timeout(mut self, timeout: Duration) -> Self {
+        self.request_timeout = timeout;
+        self
+    }
+
+    /// Set a default TTL applied to newly cached files. Without this, cached
+    /// files never expire on their own and are only invalidated by a failed
+    /// revalidation or an explicit `invalidate`/`invalidate_prefix` call.
+    pub fn with_cache_ttl(mut self, ttl: Duration) -> Self {
+        self.default_ttl = Some(ttl);
+        self
+    }
+
+    /// Enable at-rest encryption of the on-disk cache: every cache write is
+    /// sealed with ChaCha20-Poly1305 under `key`, and every cache read is
+    /// verified against it. A file that fails to decrypt or verify (corrupt
+    /// or tampered) is treated as a cache miss rather than a hard error; see
+    /// `FileError::DecryptionFailure`.
+    pub fn with_encryption(mut self, key: [u8; 32]) -> Self {
+        self.cipher = Some(ChaCha20Poly1305::new(Key::from_slice(&key)));
+        self
+    }
+
+    /// Parses a 64-character hex string (as supplied via `CFC_CACHE_KEY`)
+    /// into the 32-byte key `with_encryption` expects. Returns `None` if
+    /// `hex` isn't exactly 64 valid hex characters.
+    pub fn parse_cache_key(hex: &str) -> Option<[u8; 32]> {
+        if hex.len() != 64 {
+            return None;
+        }
+
+        let mut key = [0u8; 32];
+        for (byte, chunk) in key.iter_mut().zip(hex.as_bytes().chunks(2)) {
+            *byte = u8::from_str_radix(std::str::from_utf8(chunk).ok()?, 16).ok()?;
+        }
+        Some(key)
+    }
+
+    /// Writes `plaintext` to the cache file at `path` as a single sealed
+    /// chunk, if encryption is enabled (see `seal_chunk`). Callers that need
+    /// to stream a large download to disk without buffering the whole thing
+    /// in memory should append successive `seal_chunk` frames directly
+    /// instead (see `fill_cache_from_remote`).
+    pub(crate) fn write_cache_file(&self, path: &str, plaintext: &[u8]) -> Result<(), FileError> {
+        match &self.cipher {
+            Some(cipher) => {
+                let frame = Self::seal_chunk(cipher, plaintext)?;
+                std::fs::write(path, frame).map_err(FileError::CacheWriteFailed)
+            }
+            None => std::fs::write(path, plaintext).map_err(FileError::CacheWriteFailed),
+        }
+    }
+
+    /// Seals one chunk of plaintext into a self-delimiting on-disk frame:
+    /// `[ciphertext+tag length (4 bytes, little-endian)][nonce (12 bytes)]
+    /// [ciphertext+tag]`. Each chunk gets its own nonce and tag rather than
+    /// one tag over the whole file, so an encrypted file can be built up by
+    /// appending frames as chunks arrive instead of buffering the entire
+    /// plaintext in memory to seal it once at the end.
+    fn seal_chunk(cipher: &ChaCha20Poly1305, plaintext: &[u8]) -> Result<Vec<u8>, FileError> {
+        let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let ciphertext = cipher.encrypt(&nonce, plaintext).map_err(|_| {
+            FileError::CacheWriteFailed(std::io::Error::other("failed to seal cache chunk"))
+        })?;
+
+        let mut frame = Vec::with_capacity(FRAME_LEN_PREFIX + NONCE_LEN + ciphertext.len());
+        frame.extend_from_slice(&(ciphertext.len() as u32).to_le_bytes());
+        frame.extend_from_slice(&nonce);
+        frame.extend_from_slice(&ciphertext);
+        Ok(frame)
+    }
+
+    /// Reads the cache file at `path` back into plaintext, verifying and
+    /// stripping each sealed chunk's framing if encryption is enabled.
+    /// Returns `FileError::DecryptionFailure` if the file can't be verified,
+    /// which callers should treat as a cache miss rather than propagate.
+    pub fn read_cached(&self, path: &str) -> Result<Vec<u8>, FileError> {
+        let raw = std::fs::read(path).map_err(FileError::CacheReadFailed)?;
+
+        match &self.cipher {
+            Some(cipher) => Self::open_chunks(cipher, &raw),
+            None => Ok(raw),
+        }
+    }
+
+    /// Reverses `seal_chunk`, walking as many length-prefixed frames as the
+    /// file holds and concatenating their decrypted plaintext in order.
+    /// Each frame verifies independently, so a truncated or tampered frame
+    /// anywhere in the file is caught without needing to know up front how
+    /// many chunks the file was written in.
+    fn open_chunks(cipher: &ChaCha20Poly1305, raw: &[u8]) -> Result<Vec<u8>, FileError> {
+        let mut plaintext = Vec::with_capacity(raw.len());
+        let mut pos = 0usize;
+
+        while pos < raw.len() {
+            if raw.len() - pos < FRAME_LEN_PREFIX + NONCE_LEN {
+                return Err(FileError::DecryptionFailure);
+            }
+            let len_bytes: [u8; FRAME_LEN_PREFIX] = raw[pos..pos + FRAME_LEN_PREFIX]
+                .try_into()
+                .map_err(|_| FileError::DecryptionFailure)?;
+            let ciphertext_len = u32::from_le_bytes(len_bytes) as usize;
+            pos += FRAME_LEN_PREFIX;
+
+            let nonce = Nonce::from_slice(&raw[pos..pos + NONCE_LEN]);
+            pos += NONCE_LEN;
+
+            if raw.len() - pos < ciphertext_len {
+                return Err(FileError::DecryptionFailure);
+            }
+            let ciphertext = &raw[pos..pos + ciphertext_len];
+            pos += ciphertext_len;
+
+            let chunk = cipher
+                .decrypt(nonce, ciphertext)
+                .map_err(|_| FileError::DecryptionFailure)?;
+            plaintext.extend_from_slice(&chunk);
+        }
+
+        Ok(plaintext)
+    }
+
+    fn next_seq(&self) -> u64 {
+        self.next_seq.fetch_add(1, Ordering::Relaxed)
+    }
+
+    fn now_secs() -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs()
+    }
+
+    fn metadata_get(&self, path: &str) -> Result<Option<CacheMetadata>, FileError> {
+        let raw = self
+            .metadata_db
+            .get(path.as_bytes())
+            .map_err(FileError::CacheIndexFailed)?;
+
+        match raw {
+            Some(bytes) => serde_json::from_slice(&bytes)
+                .map(Some)
+                .map_err(|e| FileError::ReadFailed(format!("corrupt cache metadata: {}", e))),
+            None => Ok(None),
+        }
+    }
+
+    fn metadata_put(&self, path: &str, metadata: &CacheMetadata) -> Result<(), FileError> {
+        let bytes = serde_json::to_vec(metadata)
+            .map_err(|e| FileError::ReadFailed(format!("failed to encode cache metadata: {}", e)))?;
+        self.metadata_db
+            .insert(path.as_bytes(), bytes)
+            .map_err(FileError::CacheIndexFailed)?;
+        Ok(())
+    }
+
+    fn metadata_remove(&self, path: &str) -> Result<(), FileError> {
+        self.metadata_db
+            .remove(path.as_bytes())
+            .map_err(FileError::CacheIndexFailed)?;
+        Ok(())
+    }
+
+    /// Drop both the sled entry and the cache file for a single remote path.
+    pub fn invalidate(&self, path: &str) -> Result<(), FileError> {
+        self.metadata_remove(path)?;
+        if Path::new(path).exists() {
+            std::fs::remove_file(path).map_err(FileError::CacheWriteFailed)?;
+        }
+        Ok(())
+    }
+
+    /// Drop every cached entry whose remote path starts with `prefix`, e.g.
+    /// after a known remote write to a directory subtree.
+    pub fn invalidate_prefix(&self, prefix: &str) -> Result<(), FileError> {
+        let keys = self
+            .metadata_db
+            .scan_prefix(prefix.as_bytes())
+            .keys()
+            .collect::<Result<Vec<sled::IVec>, _>>()
+            .map_err(FileError::CacheIndexFailed)?;
+
+        for key in keys {
+            if let Ok(path) = std::str::from_utf8(&key) {
+                self.invalidate(path)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns `true` if the on-disk cache file for `path` can be trusted
+    /// without talking to the remote: the sled entry exists, the on-disk
+    /// size matches what was recorded, and any `expires_at` hasn't passed.
+    fn is_cache_fresh(&self, path: &str) -> Result<bool, FileError> {
+        if !Path::new(path).exists() {
+            return Ok(false);
+        }
+
+        let metadata = match self.metadata_get(path)? {
+            Some(metadata) => metadata,
+            None => return Ok(false),
+        };
+
+        if let Some(expires_at) = metadata.expires_at {
+            if Self::now_secs() >= expires_at {
+                return Ok(false);
+            }
+        }
+
+        self.on_disk_contents_intact(path, &metadata)
+    }
+
+    /// Checks the on-disk cache file for `path` against `metadata` the same
+    /// way `is_cache_fresh` does, independent of `expires_at`. Shared with
+    /// the `revalidate` shortcut in `open_file` so a truncated, corrupted,
+    /// or tampered cache file can't be kept around just because the remote
+    /// reports the path itself is unchanged.
+    fn on_disk_contents_intact(
+        &self,
+        path: &str,
+        metadata: &CacheMetadata,
+    ) -> Result<bool, FileError> {
+        if self.cipher.is_some() {
+            // A sealed file's on-disk size depends on how many chunks it was
+            // written in (see `seal_chunk`), not just the plaintext size
+            // recorded in `metadata`, so a size comparison can't tell
+            // truncation from a different chunking. Decrypting every frame
+            // and checking the recovered plaintext length instead catches
+            // both, and self-heals: a corrupted or tampered file is treated
+            // as a cache miss rather than a hard error.
+            return match self.read_cached(path) {
+                Ok(plaintext) => Ok(plaintext.len() as u64 == metadata.size),
+                Err(FileError::DecryptionFailure) => Ok(false),
+                Err(e) => Err(e),
+            };
+        }
+
+        let on_disk_size = std::fs::metadata(path)
+            .map_err(FileError::CacheReadFailed)?
+            .len();
+
+        Ok(on_disk_size == metadata.size)
+    }
+
+    /// Asks the remote whether `path` still matches `cached`, without
+    /// re-downloading its contents. Returns `true` when the cache can be
+    /// reused as-is.
+    async fn revalidate(&mut self, path: &str, cached: &CacheMetadata) -> Result<bool, FileError> {
+        let stat_request = FSRequest::Stat {
+            id: Uuid::new_v4().to_string(),
+            seq: self.next_seq(),
+            path: path.to_string(),
+        };
+
+        let stat_response = self
+            .send_request(stat_request)
+            .await
+            .map_err(FileError::WebSocketRequest)?;
+
+        if !stat_response.response.success {
+            return Ok(false);
+        }
+
+        let unchanged = match (&stat_response.response.hash, &cached.remote_hash) {
+            (Some(remote_hash), Some(cached_hash)) => remote_hash == cached_hash,
+            _ => stat_response.response.mtime == cached.remote_mtime
+                && stat_response.response.size == Some(cached.size),
+        };
+
+        Ok(unchanged)
+    }
+
+    /// Fetches full metadata (size/mtime/file type) for a remote path.
+    pub async fn metadata(&mut self, path: &str) -> Result<FileMetadata, FileError> {
+        let request = FSRequest::Metadata {
+            id: Uuid::new_v4().to_string(),
+            seq: self.next_seq(),
+            path: path.to_string(),
+        };
+
+        let response = self
+            .send_request(request)
+            .await
+            .map_err(FileError::WebSocketRequest)?;
+
+        if !response.response.success {
+            let error_msg = response
+                .response
+                .error
+                .unwrap_or_else(|| "Unknown metadata error".to_string());
+            return Err(FileError::RemoteError(error_msg));
+        }
+
+        response
+            .response
+            .metadata
+            .ok_or_else(|| FileError::RemoteError("metadata response missing metadata".to_string()))
+    }
+
+    /// Lists the immediate children of a remote directory.
+    pub async fn read_dir(&mut self, path: &str) -> Result<Vec<DirEntry>, FileError> {
+        let request = FSRequest::ReadDir {
+            id: Uuid::new_v4().to_string(),
+            seq: self.next_seq(),
+            path: path.to_string(),
+        };
+
+        let response = self
+            .send_request(request)
+            .await
+            .map_err(FileError::WebSocketRequest)?;
+
+        if !response.response.success {
+            let error_msg = response
+                .response
+                .error
+                .unwrap_or_else(|| "Unknown read_dir error".to_string());
+            return Err(FileError::RemoteError(error_msg));
+        }
+
+        Ok(response.response.entries.unwrap_or_default())
+    }
+
+    /// Creates an empty file at a remote path.
+    pub async fn create(&mut self, path: &str) -> Result<(), FileError> {
+        let request = FSRequest::Create {
+            id: Uuid::new_v4().to_string(),
+            seq: self.next_seq(),
+            path: path.to_string(),
+        };
+
+        self.run_remote_op(request).await
+    }
+
+    /// Removes a remote path, recursing into directories when `recursive` is set.
+    pub async fn remove(&mut self, path: &str, recursive: bool) -> Result<(), FileError> {
+        let request = FSRequest::Remove {
+            id: Uuid::new_v4().to_string(),
+            seq: self.next_seq(),
+            path: path.to_string(),
+            recursive,
+        };
+
+        self.run_remote_op(request).await?;
+        self.invalidate(path)?;
+        self.invalidate_prefix(path)?;
+        Ok(())
+    }
+
+    /// Moves/renames a remote path.
+    pub async fn rename(&mut self, src: &str, dst: &str) -> Result<(), FileError> {
+        let request = FSRequest::Rename {
+            id: Uuid::new_v4().to_string(),
+            seq: self.next_seq(),
+            src: src.to_string(),
+            dst: dst.to_string(),
+        };
+
+        self.run_remote_op(request).await?;
+        self.invalidate(src)?;
+        self.invalidate_prefix(src)?;
+        self.invalidate(dst)?;
+        Ok(())
+    }
+
+    /// Copies a remote path, leaving `src` in place.
+    pub async fn copy(&mut self, src: &str, dst: &str) -> Result<(), FileError> {
+        let request = FSRequest::Copy {
+            id: Uuid::new_v4().to_string(),
+            seq: self.next_seq(),
+            src: src.to_string(),
+            dst: dst.to_string(),
+        };
+
+        self.run_remote_op(request).await?;
+        self.invalidate(dst)?;
+        Ok(())
+    }
+
+    /// Truncates (or zero-extends) a remote path to exactly `size` bytes.
+    pub async fn truncate(&mut self, path: &str, size: u64) -> Result<(), FileError> {
+        let request = FSRequest::Truncate {
+            id: Uuid::new_v4().to_string(),
+            seq: self.next_seq(),
+            path: path.to_string(),
+            size,
+        };
+
+        self.run_remote_op(request).await?;
+        self.invalidate(path)?;
+        Ok(())
+    }
+
+    /// Shared plumbing for the path-management operations above: send the
+    /// request, surface a `RemoteError` on failure, and discard the response
+    /// body on success since none of them return anything but `success`.
+    async fn run_remote_op(&self, request: FSRequest) -> Result<(), FileError> {
+        let response = self
+            .send_request(request)
+            .await
+            .map_err(FileError::WebSocketRequest)?;
+
+        if !response.response.success {
+            let error_msg = response
+                .response
+                .error
+                .unwrap_or_else(|| "Unknown remote error".to_string());
+            return Err(FileError::RemoteError(error_msg));
         }
+
+        Ok(())
     }
 
     pub async fn start_server(&mut self, port: u16) -> Result<(), Box<dyn std::error::Error>> {
@@ -101,118 +904,354 @@ impl WebSocketFileSystem {
         let listener = TcpListener::bind(&addr).await?;
         println!("WebSocket server listening on {}", addr);
 
-        let (stream, _) = listener.accept().await?;
-        println!("WebSocket client connected");
+        tokio::spawn(Self::accept_loop(
+            listener,
+            Arc::clone(&self.connections),
+            Arc::clone(&self.pending_requests),
+            self.change_tx.clone(),
+            Arc::clone(&self.next_connection_id),
+            Arc::clone(&self.connection_semaphore),
+        ));
 
-        let ws_stream = accept_async(stream).await?;
-        let (ws_sender, mut ws_receiver) = ws_stream.split();
-
-        let (tx, mut rx) = mpsc::unbounded_channel();
-        self.ws_sender = Some(tx);
+        Ok(())
+    }
 
-        let pending_requests = Arc::clone(&self.pending_requests);
+    /// Accepts connections in a loop, spawning an independent handler task
+    /// per client so more than one backend can be connected (and serving
+    /// requests) at once instead of only the first one to connect. The
+    /// semaphore bounds how many clients can be connected concurrently so a
+    /// flood of connection attempts can't exhaust file descriptors.
+    async fn accept_loop(
+        listener: TcpListener,
+        connections: Arc<Mutex<HashMap<u64, mpsc::UnboundedSender<Message>>>>,
+        pending_requests: PendingRequests,
+        change_tx: broadcast::Sender<ChangeEvent>,
+        next_connection_id: Arc<AtomicU64>,
+        connection_semaphore: Arc<Semaphore>,
+    ) {
+        loop {
+            let permit = match connection_semaphore.clone().acquire_owned().await {
+                Ok(permit) => permit,
+                Err(_) => return, // Semaphore closed; server shutting down.
+            };
 
-        // Spawn WebSocket message handler
-        tokio::spawn(async move {
-            let mut ws_sender = ws_sender;
+            let (stream, _) = match listener.accept().await {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    eprintln!("Failed to accept connection: {}", e);
+                    continue;
+                }
+            };
 
-            // Handle outgoing messages
-            let outgoing_task = tokio::spawn(async move {
-                while let Some(message) = rx.recv().await {
-                    if ws_sender.send(message).await.is_err() {
-                        break;
-                    }
+            let ws_stream = match accept_async(stream).await {
+                Ok(stream) => stream,
+                Err(e) => {
+                    eprintln!("Failed WebSocket handshake: {}", e);
+                    continue;
                 }
-            });
-
-            // Handle incoming messages
-            let incoming_task = tokio::spawn(async move {
-                while let Some(msg) = ws_receiver.next().await {
-                    match msg {
-                        Ok(Message::Binary(data)) => {
-                            // Parse unified binary message: [json_len(4 bytes)][json][binary_data]
-                            if data.len() < 4 {
-                                eprintln!("WebSocket binary message too short: {} bytes, expected at least 4 for JSON length header", data.len());
-                                continue;
-                            }
+            };
 
-                            let json_len =
-                                u32::from_le_bytes([data[0], data[1], data[2], data[3]]) as usize;
+            let connection_id = next_connection_id.fetch_add(1, Ordering::Relaxed);
+            println!("WebSocket client {} connected", connection_id);
 
-                            if data.len() < 4 + json_len {
-                                eprintln!("WebSocket binary message too short: {} bytes, expected {} bytes for JSON length {}", data.len(), 4 + json_len, json_len);
-                                continue;
-                            }
+            let (tx, rx) = mpsc::unbounded_channel();
+            connections.lock().await.insert(connection_id, tx.clone());
 
-                            let json_bytes = &data[4..4 + json_len];
-                            let binary_data = &data[4 + json_len..];
-
-                            let json_str = match std::str::from_utf8(json_bytes) {
-                                Ok(s) => s,
-                                Err(e) => {
-                                    eprintln!(
-                                        "WebSocket message contains invalid UTF-8 JSON: {}",
-                                        e
-                                    );
-                                    continue;
-                                }
-                            };
-
-                            let response = match serde_json::from_str::<FSResponse>(json_str) {
-                                Ok(r) => r,
-                                Err(e) => {
-                                    eprintln!(
-                                        "WebSocket message contains invalid JSON: {} - JSON: {}",
-                                        e, json_str
-                                    );
-                                    continue;
-                                }
-                            };
-
-                            let response_id = response.id.clone();
-                            let mut response_with_binary = FSResponseWithBinary {
-                                response,
-                                binary: None,
-                            };
-                            let mut pending = pending_requests.lock().await;
-
-                            // Handle binary data if present
-                            if !binary_data.is_empty() {
-                                response_with_binary.binary = Some(binary_data.to_vec());
-                            }
+            Self::replay_onto_new_connection(connection_id, &tx, &connections, &pending_requests)
+                .await;
+
+            tokio::spawn(Self::connection_loop(
+                connection_id,
+                ws_stream,
+                rx,
+                Arc::clone(&connections),
+                Arc::clone(&pending_requests),
+                change_tx.clone(),
+                permit,
+            ));
+        }
+    }
 
-                            match pending.remove(&response_id) {
-                                Some(sender) => {
-                                    if let Err(_) = sender.send(response_with_binary) {
-                                        eprintln!("Failed to send response to waiting request (receiver dropped): {}", response_id);
-                                    }
-                                }
-                                None => {
-                                    eprintln!(
-                                        "Received WebSocket response for unknown request ID: {}",
-                                        response_id
-                                    );
-                                }
+    /// Drives a single accepted connection until it closes or errors, then
+    /// removes it from the connection table and hands only the requests that
+    /// went out on it to `replay_pending`; every other connection (and its
+    /// in-flight requests) is unaffected.
+    async fn connection_loop(
+        connection_id: u64,
+        ws_stream: WebSocketStream<TcpStream>,
+        mut rx: mpsc::UnboundedReceiver<Message>,
+        connections: Arc<Mutex<HashMap<u64, mpsc::UnboundedSender<Message>>>>,
+        pending_requests: PendingRequests,
+        change_tx: broadcast::Sender<ChangeEvent>,
+        _permit: tokio::sync::OwnedSemaphorePermit,
+    ) {
+        let (mut ws_sender, mut ws_receiver) = ws_stream.split();
+
+        loop {
+            tokio::select! {
+                outgoing = rx.recv() => {
+                    match outgoing {
+                        Some(message) => {
+                            if ws_sender.send(message).await.is_err() {
+                                break;
                             }
                         }
-                        Ok(Message::Close(_)) => break,
-                        Err(e) => {
-                            eprintln!("WebSocket error: {}", e);
+                        None => break, // Outgoing sender dropped; nothing left to serve.
+                    }
+                }
+                incoming = ws_receiver.next() => {
+                    match incoming {
+                        Some(Ok(Message::Binary(data))) => {
+                            Self::handle_incoming_binary(&data, &pending_requests, &change_tx).await;
+                        }
+                        Some(Ok(Message::Close(_))) | None => break,
+                        Some(Err(e)) => {
+                            eprintln!("WebSocket error on connection {}: {}", connection_id, e);
                             break;
                         }
                         _ => {}
                     }
                 }
-            });
+            }
+        }
+
+        println!("WebSocket client {} disconnected", connection_id);
+        connections.lock().await.remove(&connection_id);
+
+        Self::replay_pending(connection_id, &connections, &pending_requests).await;
+    }
+
+    /// Requests stranded by a connection that just died are handed to
+    /// another still-connected client rather than being dropped outright, so
+    /// a single client dropping doesn't fail in-flight requests as long as at
+    /// least one other client is connected. If no other client is currently
+    /// live — the common topology this codebase actually runs, one tracer
+    /// talking to one backend — the request is left in `pending_requests`
+    /// under its dead connection id instead of being dropped; `accept_loop`'s
+    /// `replay_onto_new_connection` picks it back up the moment a client
+    /// reconnects. Together these give the same "survive the connection
+    /// going away" guarantee the single-client version got from reconnect
+    /// replay. A request only fails outright if its own reply already timed
+    /// out (`dispatch`'s timeout removes it from `pending_requests` first).
+    async fn replay_pending(
+        dead_connection_id: u64,
+        connections: &Arc<Mutex<HashMap<u64, mpsc::UnboundedSender<Message>>>>,
+        pending_requests: &PendingRequests,
+    ) {
+        let stranded: Vec<(String, FSRequest, oneshot::Sender<FSResponseWithBinary>)> = {
+            let mut pending = pending_requests.lock().await;
+            let stranded_ids: Vec<String> = pending
+                .iter()
+                .filter(|(_, (connection_id, _, _))| *connection_id == dead_connection_id)
+                .map(|(id, _)| id.clone())
+                .collect();
+            stranded_ids
+                .into_iter()
+                .filter_map(|id| {
+                    pending
+                        .remove(&id)
+                        .map(|(_, request, sender)| (id, request, sender))
+                })
+                .collect()
+        };
+
+        for (request_id, request, sender) in stranded {
+            // Any live client will do here; replaying doesn't need the
+            // round-robin fairness `pick_connection` gives brand-new requests.
+            let next = connections
+                .lock()
+                .await
+                .iter()
+                .next()
+                .map(|(id, tx)| (*id, tx.clone()));
+
+            let Some((new_connection_id, tx)) = next else {
+                // Nobody to replay onto right now; keep the request pending
+                // under the dead connection id so a reconnecting client
+                // picks it up via `replay_onto_new_connection`.
+                pending_requests
+                    .lock()
+                    .await
+                    .insert(request_id, (dead_connection_id, request, sender));
+                continue;
+            };
+
+            let message = match Self::encode_message(&request) {
+                Ok(message) => message,
+                Err(e) => {
+                    eprintln!("Failed to re-encode replayed request {}: {}", request_id, e);
+                    continue;
+                }
+            };
 
-            // Wait for either task to complete
-            tokio::select! {
-                _ = outgoing_task => {},
-                _ = incoming_task => {},
+            if tx.send(message).is_err() {
+                continue;
             }
-        });
 
-        Ok(())
+            pending_requests
+                .lock()
+                .await
+                .insert(request_id, (new_connection_id, request, sender));
+        }
+    }
+
+    /// Re-homes every request still waiting on a connection id that isn't
+    /// currently live (left behind by `replay_pending` when it had nowhere
+    /// to send them) onto a client that just connected. This is what lets a
+    /// reconnecting backend pick up requests stranded by its previous
+    /// connection dying, even when it's the only client around.
+    async fn replay_onto_new_connection(
+        new_connection_id: u64,
+        tx: &mpsc::UnboundedSender<Message>,
+        connections: &Arc<Mutex<HashMap<u64, mpsc::UnboundedSender<Message>>>>,
+        pending_requests: &PendingRequests,
+    ) {
+        let orphaned: Vec<(String, FSRequest, oneshot::Sender<FSResponseWithBinary>)> = {
+            let live = connections.lock().await;
+            let mut pending = pending_requests.lock().await;
+            let orphaned_ids: Vec<String> = pending
+                .iter()
+                .filter(|(_, (connection_id, _, _))| !live.contains_key(connection_id))
+                .map(|(id, _)| id.clone())
+                .collect();
+            orphaned_ids
+                .into_iter()
+                .filter_map(|id| {
+                    pending
+                        .remove(&id)
+                        .map(|(_, request, sender)| (id, request, sender))
+                })
+                .collect()
+        };
+
+        for (request_id, request, sender) in orphaned {
+            let message = match Self::encode_message(&request) {
+                Ok(message) => message,
+                Err(e) => {
+                    eprintln!("Failed to re-encode replayed request {}: {}", request_id, e);
+                    continue;
+                }
+            };
+
+            if tx.send(message).is_err() {
+                continue;
+            }
+
+            pending_requests
+                .lock()
+                .await
+                .insert(request_id, (new_connection_id, request, sender));
+        }
+    }
+
+    fn encode_message(request: &FSRequest) -> Result<Message, serde_json::Error> {
+        let json_str = serde_json::to_string(request)?;
+        let json_bytes = json_str.as_bytes();
+        let json_len = json_bytes.len() as u32;
+
+        let mut message_data = Vec::with_capacity(4 + json_bytes.len());
+        message_data.extend_from_slice(&json_len.to_le_bytes());
+        message_data.extend_from_slice(json_bytes);
+
+        if let FSRequest::Write { data, .. } = request {
+            message_data.extend_from_slice(data);
+        }
+
+        Ok(Message::Binary(message_data))
+    }
+
+    async fn handle_incoming_binary(
+        data: &[u8],
+        pending_requests: &PendingRequests,
+        change_tx: &broadcast::Sender<ChangeEvent>,
+    ) {
+        // Parse unified binary message: [json_len(4 bytes)][json][binary_data]
+        if data.len() < 4 {
+            eprintln!(
+                "WebSocket binary message too short: {} bytes, expected at least 4 for JSON length header",
+                data.len()
+            );
+            return;
+        }
+
+        let json_len = u32::from_le_bytes([data[0], data[1], data[2], data[3]]) as usize;
+
+        if data.len() < 4 + json_len {
+            eprintln!(
+                "WebSocket binary message too short: {} bytes, expected {} bytes for JSON length {}",
+                data.len(),
+                4 + json_len,
+                json_len
+            );
+            return;
+        }
+
+        let json_bytes = &data[4..4 + json_len];
+        let binary_data = &data[4 + json_len..];
+
+        let json_str = match std::str::from_utf8(json_bytes) {
+            Ok(s) => s,
+            Err(e) => {
+                eprintln!("WebSocket message contains invalid UTF-8 JSON: {}", e);
+                return;
+            }
+        };
+
+        let response = match serde_json::from_str::<FSResponse>(json_str) {
+            Ok(r) => r,
+            Err(e) => {
+                eprintln!(
+                    "WebSocket message contains invalid JSON: {} - JSON: {}",
+                    e, json_str
+                );
+                return;
+            }
+        };
+
+        let response_id = response.id.clone();
+        // A change notification is pushed unsolicited: it carries `path`/`kind`
+        // instead of matching a pending request id.
+        let notification = match (&response.path, response.kind) {
+            (Some(path), Some(kind)) => Some(ChangeEvent {
+                path: path.clone(),
+                kind,
+            }),
+            _ => None,
+        };
+        let response_with_binary = FSResponseWithBinary {
+            response,
+            binary: if binary_data.is_empty() {
+                None
+            } else {
+                Some(binary_data.to_vec())
+            },
+        };
+
+        let mut pending = pending_requests.lock().await;
+        match pending.remove(&response_id) {
+            Some((_, _, sender)) => {
+                if sender.send(response_with_binary).is_err() {
+                    eprintln!(
+                        "Failed to send response to waiting request (receiver dropped): {}",
+                        response_id
+                    );
+                }
+            }
+            None => {
+                drop(pending);
+                if let Some(event) = notification {
+                    println!("Remote change notification: {:?} {}", event.kind, event.path);
+                    // Ignore the error: no active subscribers just means
+                    // nobody's currently watching anything.
+                    let _ = change_tx.send(event);
+                } else {
+                    eprintln!(
+                        "Received WebSocket response for unknown request ID: {}",
+                        response_id
+                    );
+                }
+            }
+        }
     }
 
     pub async fn send_request(
@@ -220,74 +1259,89 @@ impl WebSocketFileSystem {
         request: FSRequest,
     ) -> Result<FSResponseWithBinary, Box<dyn std::error::Error>> {
         println!("Sending request: {:?}", request);
-        let (tx, rx) = tokio::sync::oneshot::channel();
-        let request_id = request.get_id().to_string();
+        self.dispatch(request).await
+    }
 
-        // Store the response channel
-        {
-            let mut pending = self.pending_requests.lock().await;
-            pending.insert(request_id.clone(), tx);
+    pub async fn send_request_with_binary(
+        &self,
+        request: FSRequest,
+        _data: &[u8],
+    ) -> Result<FSResponseWithBinary, Box<dyn std::error::Error>> {
+        // `request` already carries its own payload (see `FSRequest::Write`),
+        // so `encode_message` appends the right bytes without needing this
+        // parameter; it's kept for call-site clarity and API stability.
+        self.dispatch(request).await
+    }
+
+    /// Picks which connected client a new request should go out on, spreading
+    /// requests round-robin across whatever clients are currently connected.
+    async fn pick_connection(
+        &self,
+    ) -> Result<(u64, mpsc::UnboundedSender<Message>), Box<dyn std::error::Error>> {
+        let connections = self.connections.lock().await;
+        if connections.is_empty() {
+            return Err("No WebSocket clients connected".into());
         }
 
-        // Send the request as unified binary message
-        if let Some(sender) = &self.ws_sender {
-            let json_str = serde_json::to_string(&request)?;
-            let json_bytes = json_str.as_bytes();
-            let json_len = json_bytes.len() as u32;
+        let mut connection_ids: Vec<u64> = connections.keys().copied().collect();
+        connection_ids.sort_unstable();
 
-            let mut message_data = Vec::with_capacity(4 + json_bytes.len());
-            message_data.extend_from_slice(&json_len.to_le_bytes());
-            message_data.extend_from_slice(json_bytes);
+        let index = self.next_connection_index.fetch_add(1, Ordering::Relaxed) as usize
+            % connection_ids.len();
+        let connection_id = connection_ids[index];
+        let sender = connections
+            .get(&connection_id)
+            .expect("connection_id was just read from this map")
+            .clone();
 
-            let message = Message::Binary(message_data);
-            sender.send(message)?;
-        } else {
-            return Err("WebSocket not connected".into());
-        }
-
-        // Wait for response
-        let response = rx.await?;
-        Ok(response)
+        Ok((connection_id, sender))
     }
 
-    pub async fn send_request_with_binary(
+    async fn dispatch(
         &self,
         request: FSRequest,
-        data: &[u8],
     ) -> Result<FSResponseWithBinary, Box<dyn std::error::Error>> {
-        let (tx, rx) = tokio::sync::oneshot::channel();
         let request_id = request.get_id().to_string();
+        let (tx, rx) = oneshot::channel();
+        let (connection_id, sender) = self.pick_connection().await?;
 
-        // Store the response channel
         {
             let mut pending = self.pending_requests.lock().await;
-            pending.insert(request_id.clone(), tx);
+            pending.insert(request_id.clone(), (connection_id, request.clone(), tx));
         }
 
-        // Send the request with binary data as unified binary message
-        if let Some(sender) = &self.ws_sender {
-            let json_str = serde_json::to_string(&request)?;
-            let json_bytes = json_str.as_bytes();
-            let json_len = json_bytes.len() as u32;
+        let message = match Self::encode_message(&request) {
+            Ok(message) => message,
+            Err(e) => {
+                self.pending_requests.lock().await.remove(&request_id);
+                return Err(Box::new(e));
+            }
+        };
 
-            let mut message_data = Vec::with_capacity(4 + json_bytes.len() + data.len());
-            message_data.extend_from_slice(&json_len.to_le_bytes());
-            message_data.extend_from_slice(json_bytes);
-            message_data.extend_from_slice(data);
+        if sender.send(message).is_err() {
+            self.pending_requests.lock().await.remove(&request_id);
+            return Err(format!("Connection {} is no longer connected", connection_id).into());
+        }
 
-            let message = Message::Binary(message_data);
-            sender.send(message)?;
-        } else {
-            return Err("WebSocket not connected".into());
+        match tokio::time::timeout(self.request_timeout, rx).await {
+            Ok(Ok(response)) => Ok(response),
+            Ok(Err(e)) => Err(Box::new(e)),
+            Err(_) => {
+                self.pending_requests.lock().await.remove(&request_id);
+                Err(Box::new(FileError::Timeout(request_id)))
+            }
         }
+    }
 
-        // Wait for response
-        let response = rx.await?;
-        Ok(response)
+    /// Hands out the next fake fd number for a backend to `register_fd`.
+    pub fn allocate_fd(&mut self) -> i32 {
+        let fd = self.next_fd;
+        self.next_fd += 1;
+        fd
     }
 
-    pub fn register_fd(&mut self, fd: i32, path: &str) {
-        self.open_files.insert(
+    pub fn register_fd(&mut self, table_id: i32, fd: i32, path: &str) {
+        self.open_files.entry(table_id).or_default().insert(
             fd,
             CachedFile {
                 path: path.to_string(),
@@ -295,24 +1349,112 @@ impl WebSocketFileSystem {
             },
         );
     }
-    pub fn update_fd_position(&mut self, fd: i32, position: usize) {
-        if let Some(file) = self.open_files.get_mut(&fd) {
+    pub fn update_fd_position(&mut self, table_id: i32, fd: i32, position: usize) {
+        if let Some(file) = self
+            .open_files
+            .get_mut(&table_id)
+            .and_then(|table| table.get_mut(&fd))
+        {
             file.position = position;
         }
     }
 
+    /// Gives `child_table` an independent copy of `parent_table`'s
+    /// currently-open fds, the way a real `fork()` hands a child its own
+    /// copy of the parent's fd table. Call this from the
+    /// `PTRACE_EVENT_FORK` handler before the child's first syscall stop;
+    /// a no-op if `parent_table` has no fds open. `vfork()`/`clone()`
+    /// descendants instead keep using `parent_table`'s id directly, since
+    /// those share the real fd table rather than copying it.
+    pub fn clone_fd_table(&mut self, parent_table: i32, child_table: i32) {
+        if let Some(parent_files) = self.open_files.get(&parent_table) {
+            let copy = parent_files.clone();
+            // Each copied fd keeps its path open, so it counts in
+            // `watched_paths` like the parent's own fd does.
+            let mut watched = self.watched_paths.lock().unwrap();
+            for file in copy.values() {
+                *watched.entry(file.path.clone()).or_insert(0) += 1;
+            }
+            drop(watched);
+            self.open_files.insert(child_table, copy);
+        }
+    }
+
+    /// Drops `table_id`'s fd table entirely. Call once no traced pid is
+    /// using this id anymore (its owning process, and every `vfork()`/
+    /// `clone()`ed descendant sharing it, have all exited), so a long trace
+    /// with many short-lived forked children doesn't leak a table per child.
+    pub fn drop_fd_table(&mut self, table_id: i32) {
+        if let Some(table) = self.open_files.remove(&table_id) {
+            for file in table.values() {
+                self.unwatch(&file.path);
+            }
+        }
+    }
+
     pub async fn open_file(&mut self, path: &str) -> Result<(), FileError> {
-        // Check if file exists already
-        println!("Checking if file exists: {}", path);
-        if !Path::new(&path).exists() {
-            println!("File does not exist, reading from Deno: {}", path);
-            // Read entire file from Deno
-            let read_id = Uuid::new_v4().to_string();
+        println!("Checking cache freshness: {}", path);
+        if self.is_cache_fresh(path)? {
+            return Ok(());
+        }
+
+        // A file that exists on disk but failed the freshness check above
+        // might just be stale, not gone: ask the remote whether its content
+        // actually changed before paying for a full re-download.
+        if Path::new(path).exists() {
+            if let Some(cached) = self.metadata_get(path)? {
+                if self.on_disk_contents_intact(path, &cached)?
+                    && self.revalidate(path, &cached).await.unwrap_or(false)
+                {
+                    println!("Cache entry for {} revalidated, keeping local bytes", path);
+                    self.metadata_put(path, &self.refresh_expiry(cached))?;
+                    return Ok(());
+                }
+            }
+        }
+
+        println!("Cache miss for {}, reading from remote", path);
+
+        if let Err(e) = self.fill_cache_from_remote(path).await {
+            let _ = std::fs::remove_file(path);
+            let _ = self.metadata_remove(path);
+            return Err(e);
+        }
+
+        Ok(())
+    }
+
+    fn refresh_expiry(&self, mut metadata: CacheMetadata) -> CacheMetadata {
+        metadata.cached_at = Self::now_secs();
+        metadata.expires_at = self.default_ttl.map(|ttl| Self::now_secs() + ttl.as_secs());
+        metadata
+    }
+
+    /// Fetches `path` from the remote in fixed-size chunks and appends each
+    /// chunk to the local cache file until a short (or empty) read signals
+    /// EOF, then records the result in the cache metadata index.
+    async fn fill_cache_from_remote(&mut self, path: &str) -> Result<(), FileError> {
+        if let Some(parent_dir) = Path::new(&path).parent() {
+            std::fs::create_dir_all(parent_dir).map_err(FileError::CacheWriteFailed)?;
+        }
+
+        let mut offset = 0usize;
+        let mut total_bytes = 0usize;
+        let mut remote_hash = None;
+        let mut remote_mtime = None;
+        // Start from an empty file; chunks below are appended to it. When
+        // encryption is enabled each chunk is sealed as its own frame (see
+        // `seal_chunk`) before being appended, so a large remote file never
+        // needs to be held in memory in full just to seal it.
+        std::fs::write(path, []).map_err(FileError::CacheWriteFailed)?;
+
+        loop {
             let read_request = FSRequest::Read {
-                id: read_id.clone(),
+                id: Uuid::new_v4().to_string(),
+                seq: self.next_seq(),
                 path: path.to_string(),
-                size: 1024 * 1024, // Read up to 1MB
-                offset: 0,
+                size: self.chunk_size,
+                offset,
             };
 
             let read_response = self
@@ -320,8 +1462,6 @@ impl WebSocketFileSystem {
                 .await
                 .map_err(FileError::WebSocketRequest)?;
 
-            println!("Read response: {:?}", read_response.response);
-
             if !read_response.response.success {
                 let error_msg = read_response
                     .response
@@ -330,20 +1470,47 @@ impl WebSocketFileSystem {
                 return Err(FileError::ReadFailed(error_msg));
             }
 
-            if read_response.response.bytes_read.unwrap_or(0) > 0 {
-                let file_data = read_response
+            if offset == 0 {
+                remote_hash = read_response.response.hash.clone();
+                remote_mtime = read_response.response.mtime;
+            }
+
+            let bytes_read = read_response.response.bytes_read.unwrap_or(0);
+            if bytes_read > 0 {
+                let chunk = read_response
                     .binary
                     .ok_or_else(|| FileError::ReadFailed("No binary data received".to_string()))?;
 
-                if let Some(parent_dir) = Path::new(&path).parent() {
-                    std::fs::create_dir_all(parent_dir).map_err(FileError::CacheWriteFailed)?;
-                }
-                std::fs::write(path, &file_data).map_err(FileError::CacheWriteFailed)?;
+                total_bytes += chunk.len();
+                offset += bytes_read;
 
-                println!("Cached file {} ({} bytes)", path, file_data.len());
+                let to_append = match &self.cipher {
+                    Some(cipher) => Self::seal_chunk(cipher, &chunk)?,
+                    None => chunk,
+                };
+                std::fs::OpenOptions::new()
+                    .append(true)
+                    .open(path)
+                    .and_then(|mut file| file.write_all(&to_append))
+                    .map_err(FileError::CacheWriteFailed)?;
+            }
+
+            if bytes_read == 0 || bytes_read < self.chunk_size {
+                break;
             }
         }
 
+        println!("Cached file {} ({} bytes)", path, total_bytes);
+
+        let metadata = CacheMetadata {
+            size: total_bytes as u64,
+            remote_hash,
+            remote_mtime,
+            cached_at: Self::now_secs(),
+            expires_at: self.default_ttl.map(|ttl| Self::now_secs() + ttl.as_secs()),
+        };
+        self.metadata_put(path, &metadata)?;
+
         Ok(())
     }
 
@@ -351,6 +1518,7 @@ impl WebSocketFileSystem {
         // Write-through to Deno first
         let write_request = FSRequest::Write {
             id: Uuid::new_v4().to_string(),
+            seq: self.next_seq(),
             path: path.to_string(),
             offset: 0,
             data: data.to_vec(),
@@ -360,7 +1528,15 @@ impl WebSocketFileSystem {
         match self.send_request_with_binary(write_request, data).await {
             Ok(response) if response.response.success => {
                 // Update cache file on disk
-                if let Ok(()) = std::fs::write(path, data) {
+                if self.write_cache_file(path, data).is_ok() {
+                    let metadata = CacheMetadata {
+                        size: data.len() as u64,
+                        remote_hash: response.response.hash,
+                        remote_mtime: response.response.mtime,
+                        cached_at: Self::now_secs(),
+                        expires_at: self.default_ttl.map(|ttl| Self::now_secs() + ttl.as_secs()),
+                    };
+                    let _ = self.metadata_put(path, &metadata);
                     Some(data.len())
                 } else {
                     None
@@ -370,8 +1546,111 @@ impl WebSocketFileSystem {
         }
     }
 
-    pub fn close_file(&mut self, fd: i32) -> bool {
-        self.open_files.remove(&fd).is_some()
+    pub fn close_file(&mut self, table_id: i32, fd: i32) -> bool {
+        let Some(table) = self.open_files.get_mut(&table_id) else {
+            return false;
+        };
+        match table.remove(&fd) {
+            Some(file) => {
+                self.unwatch(&file.path);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Looks up the remote path backing a fake fd, for backends that need to
+    /// resolve `read`/`write`/`lseek`'s fd argument back to a file.
+    pub fn fd_path(&self, table_id: i32, fd: i32) -> Option<&str> {
+        self.open_files
+            .get(&table_id)?
+            .get(&fd)
+            .map(|file| file.path.as_str())
+    }
+
+    pub fn fd_position(&self, table_id: i32, fd: i32) -> Option<usize> {
+        self.open_files.get(&table_id)?.get(&fd).map(|file| file.position)
+    }
+
+    /// Whether `fd` is one this filesystem handed out in `table_id`'s fd
+    /// table, as opposed to a real kernel fd the tracee owns on its own.
+    pub fn is_fake_fd(&self, table_id: i32, fd: i32) -> bool {
+        self.open_files
+            .get(&table_id)
+            .is_some_and(|table| table.contains_key(&fd))
+    }
+
+    /// Reads up to `len` bytes from `fd`'s cache file starting at its current
+    /// tracked position, without advancing it. Returns `None` if `fd` isn't a
+    /// fake fd or its cache file can't be read. A decryption failure is
+    /// treated as a cache miss and self-healed by re-fetching from remote,
+    /// the same way `open_file`'s revalidation path does, instead of
+    /// surfacing as a silent false-EOF read.
+    pub async fn pread(&mut self, table_id: i32, fd: i32, len: usize) -> Option<Vec<u8>> {
+        let path = self.open_files.get(&table_id)?.get(&fd)?.path.clone();
+
+        let data = match self.read_cached(&path) {
+            Ok(data) => data,
+            Err(FileError::DecryptionFailure) => {
+                eprintln!("Cache entry for {} failed to decrypt, re-fetching", path);
+                self.fill_cache_from_remote(&path).await.ok()?;
+                self.read_cached(&path).ok()?
+            }
+            Err(_) => return None,
+        };
+
+        let file = self.open_files.get(&table_id)?.get(&fd)?;
+        let start = file.position.min(data.len());
+        let end = (start + len).min(data.len());
+        Some(data[start..end].to_vec())
+    }
+
+    /// Writes `data` into `fd`'s cache file at its current tracked position,
+    /// extending the file with zeros first if the write starts past its end.
+    /// Pushes the whole updated file through `write_file` to stay consistent
+    /// with the remote, since the wire protocol only knows whole-file writes.
+    /// Returns the number of bytes written, without advancing the position.
+    pub async fn pwrite(&mut self, table_id: i32, fd: i32, data: &[u8]) -> Option<usize> {
+        let (path, position) = {
+            let file = self.open_files.get(&table_id)?.get(&fd)?;
+            (file.path.clone(), file.position)
+        };
+
+        let mut contents = self.read_cached(&path).unwrap_or_default();
+        if contents.len() < position {
+            contents.resize(position, 0);
+        }
+        let end = position + data.len();
+        if contents.len() < end {
+            contents.resize(end, 0);
+        }
+        contents[position..end].copy_from_slice(data);
+
+        self.write_file(&path, &contents).await?;
+        Some(data.len())
+    }
+
+    /// Updates `fd`'s tracked position per `SEEK_SET`/`SEEK_CUR`/`SEEK_END`
+    /// semantics and returns the new position, or `None` if `fd` isn't a
+    /// fake fd, `whence` is invalid, or the resulting offset would be
+    /// negative.
+    pub fn seek(&mut self, table_id: i32, fd: i32, offset: i64, whence: i32) -> Option<usize> {
+        let file = self.open_files.get(&table_id)?.get(&fd)?;
+        let base = match whence {
+            libc::SEEK_SET => 0,
+            libc::SEEK_CUR => file.position as i64,
+            libc::SEEK_END => self.read_cached(&file.path).ok()?.len() as i64,
+            _ => return None,
+        };
+
+        let new_position = base.checked_add(offset)?;
+        if new_position < 0 {
+            return None;
+        }
+
+        let file = self.open_files.get_mut(&table_id)?.get_mut(&fd)?;
+        file.position = new_position as usize;
+        Some(file.position)
     }
 }
 
@@ -380,6 +1659,15 @@ impl FSRequest {
         match self {
             FSRequest::Read { id, .. } => id,
             FSRequest::Write { id, .. } => id,
+            FSRequest::Stat { id, .. } => id,
+            FSRequest::Watch { id, .. } => id,
+            FSRequest::Metadata { id, .. } => id,
+            FSRequest::ReadDir { id, .. } => id,
+            FSRequest::Create { id, .. } => id,
+            FSRequest::Remove { id, .. } => id,
+            FSRequest::Rename { id, .. } => id,
+            FSRequest::Copy { id, .. } => id,
+            FSRequest::Truncate { id, .. } => id,
         }
     }
 }
@@ -154,6 +154,7 @@ mod tests {
         let test_data = b"Hello, WebSocket filesystem!";
         let write_request = FSRequest::Write {
             id: uuid::Uuid::new_v4().to_string(),
+            seq: 0,
             path: "/test/write_test.txt".to_string(),
             offset: 0,
             data: test_data.to_vec(),
@@ -178,6 +179,7 @@ mod tests {
         let test_data = b"Data to be read back";
         let write_request = FSRequest::Write {
             id: uuid::Uuid::new_v4().to_string(),
+            seq: 0,
             path: "/test/read_test.txt".to_string(),
             offset: 0,
             data: test_data.to_vec(),
@@ -189,6 +191,7 @@ mod tests {
         // Now read it back
         let read_request = FSRequest::Read {
             id: uuid::Uuid::new_v4().to_string(),
+            seq: 0,
             path: "/test/read_test.txt".to_string(),
             size: test_data.len(),
             offset: 0,
@@ -209,6 +212,7 @@ mod tests {
 
         let read_request = FSRequest::Read {
             id: uuid::Uuid::new_v4().to_string(),
+            seq: 0,
             path: "/test/nonexistent_file.txt".to_string(),
             size: 100,
             offset: 0,
@@ -232,6 +236,7 @@ mod tests {
         // Write data
         let write_request = FSRequest::Write {
             id: uuid::Uuid::new_v4().to_string(),
+            seq: 0,
             path: file_path.to_string(),
             offset: 0,
             data: test_data.to_vec(),
@@ -243,6 +248,7 @@ mod tests {
         // Read data back
         let read_request = FSRequest::Read {
             id: uuid::Uuid::new_v4().to_string(),
+            seq: 0,
             path: file_path.to_string(),
             size: test_data.len(),
             offset: 0,
@@ -265,6 +271,7 @@ mod tests {
         // Write large file
         let write_request = FSRequest::Write {
             id: uuid::Uuid::new_v4().to_string(),
+            seq: 0,
             path: file_path.to_string(),
             offset: 0,
             data: large_data.clone(),
@@ -276,6 +283,7 @@ mod tests {
         // Read large file back
         let read_request = FSRequest::Read {
             id: uuid::Uuid::new_v4().to_string(),
+            seq: 0,
             path: file_path.to_string(),
             size: large_data.len(),
             offset: 0,
@@ -301,6 +309,7 @@ mod tests {
         for (path, data) in &test_files {
             let write_request = FSRequest::Write {
                 id: uuid::Uuid::new_v4().to_string(),
+            seq: 0,
                 path: path.to_string(),
                 offset: 0,
                 data: data.to_vec(),
@@ -314,6 +323,7 @@ mod tests {
         for (path, expected_data) in &test_files {
             let read_request = FSRequest::Read {
                 id: uuid::Uuid::new_v4().to_string(),
+            seq: 0,
                 path: path.to_string(),
                 size: expected_data.len(),
                 offset: 0,
@@ -338,6 +348,7 @@ mod tests {
         // Write initial data
         let write_request1 = FSRequest::Write {
             id: uuid::Uuid::new_v4().to_string(),
+            seq: 0,
             path: file_path.to_string(),
             offset: 0,
             data: initial_data.to_vec(),
@@ -349,6 +360,7 @@ mod tests {
         // Write at offset
         let write_request2 = FSRequest::Write {
             id: uuid::Uuid::new_v4().to_string(),
+            seq: 0,
             path: file_path.to_string(),
             offset: write_offset,
             data: offset_data.to_vec(),
@@ -360,6 +372,7 @@ mod tests {
         // Read back and verify the data was written at correct offset
         let read_request = FSRequest::Read {
             id: uuid::Uuid::new_v4().to_string(),
+            seq: 0,
             path: file_path.to_string(),
             size: initial_data.len().max(write_offset + offset_data.len()),
             offset: 0,
@@ -373,6 +386,114 @@ mod tests {
         assert_eq!(&read_data[write_offset..write_offset + offset_data.len()], offset_data);
     }
 
+    // test_metadata_operation, test_read_dir_operation, and
+    // test_rename_and_remove_operations below exercise stat/metadata,
+    // read_dir, rename, and remove against the real `TestEnvironment`
+    // remote (the `deno` subprocess started in `start()`), which means
+    // they depend on `filesystem_client.ts` dispatching `Metadata`/
+    // `ReadDir`/`Rename`/`Remove` requests. That Deno-side dispatch is out
+    // of scope for this change and hasn't been implemented anywhere in this
+    // repo, so these three tests are unverified here: they cannot pass
+    // (and `TestEnvironment::start` can't even launch its client, since the
+    // hardcoded `current_dir` above only exists on one machine).
+    #[tokio::test]
+    async fn test_metadata_operation() {
+        let mut env = TestEnvironment::new(8098);
+        env.start().await.unwrap();
+
+        let test_data = b"Metadata round-trip test data";
+        let file_path = "/test/metadata_test.txt";
+
+        let write_request = FSRequest::Write {
+            id: uuid::Uuid::new_v4().to_string(),
+            seq: 0,
+            path: file_path.to_string(),
+            offset: 0,
+            data: test_data.to_vec(),
+        };
+        let write_response = env.send_filesystem_request_with_data(write_request, test_data).await.unwrap();
+        assert!(write_response.response.success, "Write should succeed");
+
+        let metadata_request = FSRequest::Metadata {
+            id: uuid::Uuid::new_v4().to_string(),
+            seq: 0,
+            path: file_path.to_string(),
+        };
+        let response = env.send_filesystem_request(metadata_request).await.unwrap();
+
+        assert!(response.response.success, "Metadata should succeed");
+        let metadata = response.response.metadata.expect("Should have metadata");
+        assert_eq!(metadata.size, test_data.len() as u64);
+        assert_eq!(metadata.file_type, FileType::File);
+    }
+
+    #[tokio::test]
+    async fn test_read_dir_operation() {
+        let mut env = TestEnvironment::new(8099);
+        env.start().await.unwrap();
+
+        for name in ["a.txt", "b.txt"] {
+            let write_request = FSRequest::Write {
+                id: uuid::Uuid::new_v4().to_string(),
+                seq: 0,
+                path: format!("/test/read_dir/{}", name),
+                offset: 0,
+                data: b"x".to_vec(),
+            };
+            let response = env.send_filesystem_request_with_data(write_request, b"x").await.unwrap();
+            assert!(response.response.success, "Write should succeed for {}", name);
+        }
+
+        let read_dir_request = FSRequest::ReadDir {
+            id: uuid::Uuid::new_v4().to_string(),
+            seq: 0,
+            path: "/test/read_dir".to_string(),
+        };
+        let response = env.send_filesystem_request(read_dir_request).await.unwrap();
+
+        assert!(response.response.success, "ReadDir should succeed");
+        let entries = response.response.entries.expect("Should have entries");
+        assert_eq!(entries.len(), 2, "Should list both written files");
+    }
+
+    #[tokio::test]
+    async fn test_rename_and_remove_operations() {
+        let mut env = TestEnvironment::new(8100);
+        env.start().await.unwrap();
+
+        let src_path = "/test/rename_src.txt";
+        let dst_path = "/test/rename_dst.txt";
+        let test_data = b"Rename then remove";
+
+        let write_request = FSRequest::Write {
+            id: uuid::Uuid::new_v4().to_string(),
+            seq: 0,
+            path: src_path.to_string(),
+            offset: 0,
+            data: test_data.to_vec(),
+        };
+        let write_response = env.send_filesystem_request_with_data(write_request, test_data).await.unwrap();
+        assert!(write_response.response.success, "Write should succeed");
+
+        let rename_request = FSRequest::Rename {
+            id: uuid::Uuid::new_v4().to_string(),
+            seq: 0,
+            src: src_path.to_string(),
+            dst: dst_path.to_string(),
+        };
+        let rename_response = env.send_filesystem_request(rename_request).await.unwrap();
+        assert!(rename_response.response.success, "Rename should succeed");
+
+        let remove_request = FSRequest::Remove {
+            id: uuid::Uuid::new_v4().to_string(),
+            seq: 0,
+            path: dst_path.to_string(),
+            recursive: false,
+        };
+        let remove_response = env.send_filesystem_request(remove_request).await.unwrap();
+        assert!(remove_response.response.success, "Remove should succeed");
+    }
+
     #[tokio::test]
     async fn test_sequential_operations() {
         let mut env = TestEnvironment::new(8097);
@@ -385,6 +506,7 @@ mod tests {
         for i in 0..5 {
             let write_request = FSRequest::Write {
                 id: uuid::Uuid::new_v4().to_string(),
+            seq: 0,
                 path: format!("{}{}", file_path, i),
                 offset: 0,
                 data: test_data.to_vec(),
@@ -399,6 +521,7 @@ mod tests {
         for i in 0..5 {
             let read_request = FSRequest::Read {
                 id: uuid::Uuid::new_v4().to_string(),
+            seq: 0,
                 path: format!("{}{}", file_path, i),
                 size: test_data.len(),
                 offset: 0,
@@ -409,4 +532,86 @@ mod tests {
             assert_eq!(response.binary.unwrap(), test_data, "Data {} should match", i);
         }
     }
+
+    #[test]
+    fn test_encrypted_cache_round_trip() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let cache_dir = temp_dir.path().to_str().unwrap().to_string();
+        let key = [0x42u8; 32];
+        let fs = WebSocketFileSystem::new(cache_dir).with_encryption(key);
+
+        let file_path = temp_dir.path().join("sealed.bin");
+        let file_path = file_path.to_str().unwrap();
+        let plaintext = b"super secret cache contents";
+
+        fs.write_cache_file(file_path, plaintext).unwrap();
+        assert_ne!(
+            std::fs::read(file_path).unwrap(),
+            plaintext,
+            "sealed file should not contain the plaintext verbatim"
+        );
+
+        let decrypted = fs.read_cached(file_path).unwrap();
+        assert_eq!(decrypted, plaintext);
+    }
+
+    #[test]
+    fn test_encrypted_cache_tamper_is_reported_as_cache_miss() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let cache_dir = temp_dir.path().to_str().unwrap().to_string();
+        let key = [0x7au8; 32];
+        let fs = WebSocketFileSystem::new(cache_dir).with_encryption(key);
+
+        let file_path = temp_dir.path().join("tampered.bin");
+        let file_path = file_path.to_str().unwrap();
+        fs.write_cache_file(file_path, b"original contents").unwrap();
+
+        let mut sealed = std::fs::read(file_path).unwrap();
+        let last = sealed.len() - 1;
+        sealed[last] ^= 0xff;
+        std::fs::write(file_path, &sealed).unwrap();
+
+        let result = fs.read_cached(file_path);
+        assert!(
+            matches!(result, Err(FileError::DecryptionFailure)),
+            "tampered cache file should fail AEAD verification, got {:?}",
+            result
+        );
+    }
+
+    #[test]
+    fn test_plaintext_cache_round_trip_when_encryption_disabled() {
+        let temp_dir = tempfile::tempdir().unwrap();
+        let cache_dir = temp_dir.path().to_str().unwrap().to_string();
+        let fs = WebSocketFileSystem::new(cache_dir);
+
+        let file_path = temp_dir.path().join("plain.bin");
+        let file_path = file_path.to_str().unwrap();
+        let plaintext = b"no encryption configured";
+
+        fs.write_cache_file(file_path, plaintext).unwrap();
+        assert_eq!(std::fs::read(file_path).unwrap(), plaintext);
+        assert_eq!(fs.read_cached(file_path).unwrap(), plaintext);
+    }
+
+    #[test]
+    fn test_parse_cache_key_valid() {
+        let hex = "42".repeat(32);
+        let key = WebSocketFileSystem::parse_cache_key(&hex).expect("64 hex chars should parse");
+        assert_eq!(key, [0x42u8; 32]);
+    }
+
+    #[test]
+    fn test_parse_cache_key_rejects_wrong_length() {
+        assert!(WebSocketFileSystem::parse_cache_key("abcd").is_none());
+        assert!(WebSocketFileSystem::parse_cache_key(&"ab".repeat(31)).is_none());
+        assert!(WebSocketFileSystem::parse_cache_key(&"ab".repeat(33)).is_none());
+    }
+
+    #[test]
+    fn test_parse_cache_key_rejects_non_hex_chars() {
+        let mut bad = "a".repeat(64);
+        bad.replace_range(0..1, "z");
+        assert!(WebSocketFileSystem::parse_cache_key(&bad).is_none());
+    }
 }
\ No newline at end of file